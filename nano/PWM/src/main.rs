@@ -12,37 +12,44 @@ use arduino_hal::{
     prelude::*,
 };
 use avr_device::interrupt::Mutex;
+use nano_common::{
+    dither,
+    encoder::{AccelerationCurve, Accelerator, CountsPerDetent, QuadratureDecoder},
+    fade::{Easing, FadeDriver},
+    isr::IsrShared,
+    millis::Millis,
+    mix::{MixMode, Mixer},
+    pwm::{Bits, CompareWriter, Duty, Mode, Prescaler, TickTimer, Timer1Pwm},
+    queue::Queue,
+    time::{Clock, Duration},
+};
 use panic_halt as _;
 
-use core::{
-    cell::Cell,
-    mem::MaybeUninit,
-    sync::atomic::{AtomicBool, Ordering},
-};
+use core::cell::RefCell;
 
-#[allow(dead_code)]
-enum PWMAccuracy {
-    LOW,
-    MEDIUM,
-    HIGH,
+enum Event {
+    Rotated(i16),
 }
 
-impl PWMAccuracy {
-    fn val(&self) -> u16 {
-        match self {
-            PWMAccuracy::LOW => 255,
-            PWMAccuracy::MEDIUM => 511,
-            PWMAccuracy::HIGH => 1023,
-        }
-    }
-}
-
-static ROTARY_CHANGE: AtomicBool = AtomicBool::new(false);
-static VAL: Mutex<Cell<u16>> = Mutex::new(Cell::new(0));
-static ROTARY_PINS: Mutex<Cell<MaybeUninit<[Pin<Input<PullUp>, Dynamic>; 2]>>> =
-    Mutex::new(Cell::new(MaybeUninit::uninit()));
-const PWM_ACCURACY: PWMAccuracy = PWMAccuracy::HIGH;
-const BRIGHTNESS_STEP: u16 = 25;
+static EVENTS: Queue<Event, 16> = Queue::new();
+static ROTARY_PINS: IsrShared<[Pin<Input<PullUp>, Dynamic>; 2]> = IsrShared::new();
+static CLOCK: IsrShared<Millis> = IsrShared::new();
+static DECODER: Mutex<RefCell<QuadratureDecoder>> =
+    Mutex::new(RefCell::new(QuadratureDecoder::new(CountsPerDetent::Four)));
+static ACCELERATOR: Mutex<RefCell<Accelerator>> =
+    Mutex::new(RefCell::new(Accelerator::new(BRIGHTNESS_CURVE)));
+static FADER: FadeDriver<[CompareWriter<Timer1Pwm<Resolution>>; 2], 2> =
+    FadeDriver::new(Duration::from_millis(150), Easing::InOut);
+type Resolution = Bits<10>;
+const MAX: u16 = Duty::<Resolution>::MAX.get();
+/// Sweeps from one colour to the other with both fully on in the middle.
+const MIXER: Mixer = Mixer::new(MixMode::Overlap, MAX * 2);
+const BRIGHTNESS_CURVE: AccelerationCurve = AccelerationCurve {
+    slow: Duration::from_millis(150),
+    fast: Duration::from_millis(20),
+    min_step: 4,
+    max_step: 100,
+};
 
 #[arduino_hal::entry]
 fn main() -> ! {
@@ -54,56 +61,53 @@ fn main() -> ! {
         pins.d8.into_pull_up_input().downgrade(),
         pins.d2.into_pull_up_input().downgrade(),
     ];
-    avr_device::interrupt::free(|cs| {
-        ROTARY_PINS.borrow(cs).set(MaybeUninit::new(rotary_pins));
-    });
-
-    let _red_led_pin = pins.d9.into_output();
-    let _green_led_pin = pins.d10.into_output();
-
-    let timer1 = peripherals.TC1;
-    timer1.tccr1a.write(|w| {
-        w.wgm1()
-            .bits(match PWM_ACCURACY {
-                PWMAccuracy::LOW => 0b01,
-                PWMAccuracy::MEDIUM => 0b10,
-                PWMAccuracy::HIGH => 0b11,
-            })
-            .com1a()
-            .match_clear()
-            .com1b()
-            .match_clear()
-    });
-    timer1.tccr1b.write(|w| match PWM_ACCURACY {
-        PWMAccuracy::LOW => w.wgm1().bits(0b00).cs1().prescale_256(),
-        PWMAccuracy::MEDIUM => w.wgm1().bits(0b00).cs1().prescale_256(),
-        PWMAccuracy::HIGH => w.wgm1().bits(0b00).cs1().prescale_64(),
-    });
-
-    timer1
-        .ocr1b
-        .write(|w| unsafe { w.bits(PWM_ACCURACY.val()) });
-
-    peripherals.EXINT.pcicr.write(|w| unsafe { w.bits(0b100) });
+    ROTARY_PINS.init(rotary_pins);
+    // Times the detents for the acceleration
+    CLOCK.init(Millis::init(peripherals.TC0));
+
+    let timer1 =
+        Timer1Pwm::<Resolution>::new(peripherals.TC1, Mode::PhaseCorrect, Prescaler::Div64);
+    let mut red_led = timer1.d9(pins.d9.into_output());
+    let mut green_led = timer1.d10(pins.d10.into_output());
+    red_led.enable();
+    green_led.enable();
+    let ticks = TickTimer::timer2(peripherals.TC2, 1_000);
+    FADER.start(
+        [red_led.compare_writer(), green_led.compare_writer()],
+        ticks.rate(),
+    );
+
+    // Watch both encoder channels: d8 is PCINT0 and d2 is PCINT18
+    peripherals.EXINT.pcicr.write(|w| unsafe { w.bits(0b101) });
+    peripherals.EXINT.pcmsk0.write(|w| unsafe { w.bits(0b1) });
     peripherals.EXINT.pcmsk2.write(|w| unsafe { w.bits(0b100) });
 
     unsafe {
         avr_device::interrupt::enable();
     }
+    FADER.fade([0, dither::MAX_LEVEL]);
+
+    let mut val: u16 = 0;
 
     ufmt::uwriteln!(&mut serial, "Finished Setup!").void_unwrap();
     loop {
-        // if changed(&TMR_OVERFLOW) {
-        //     ufmt::uwriteln!(&mut serial, "Timer!").void_unwrap();
-        // }
-        if changed(&ROTARY_CHANGE) {
-            let val = get_from_mutex(&VAL);
-            let red = PWM_ACCURACY.val().min(val);
-            let green = PWM_ACCURACY.val().min((PWM_ACCURACY.val() * 2) - val);
+        let mut changed = false;
+        while let Some(event) = EVENTS.pop() {
+            match event {
+                Event::Rotated(step) => {
+                    val = (val as i16 + step).max(0).min(MAX as i16 * 2) as u16;
+                    changed = true;
+                }
+            }
+        }
+        if changed {
+            let (red, green) = MIXER.mix(val, MAX);
             ufmt::uwriteln!(&mut serial, "Val: {}\tRed: {}\tGreen: {}", val, red, green)
                 .void_unwrap();
-            timer1.ocr1a.write(|w| unsafe { w.bits(red) });
-            timer1.ocr1b.write(|w| unsafe { w.bits(green) });
+            FADER.fade([
+                dither::level(red as u32, MAX as u32),
+                dither::level(green as u32, MAX as u32),
+            ]);
         }
         delay_ms(50);
     }
@@ -111,37 +115,35 @@ fn main() -> ! {
 
 #[avr_device::interrupt(atmega328p)]
 #[allow(non_snake_case)]
-fn PCINT2() {
-    avr_device::interrupt::free(|cs| {
-        let rotary_pins = unsafe { &*(&*ROTARY_PINS.borrow(cs).as_ptr()).as_ptr() };
-        if rotary_pins[1].is_low() {
-            ROTARY_CHANGE.store(true, Ordering::SeqCst);
-            let val_cell = VAL.borrow(cs);
-            let val = val_cell.get();
-            if rotary_pins[0].is_low() {
-                val_cell.set((PWM_ACCURACY.val() * 2).min(val + BRIGHTNESS_STEP));
-            } else if rotary_pins[0].is_high() {
-                val_cell.set(if val > BRIGHTNESS_STEP {
-                    val - BRIGHTNESS_STEP
-                } else {
-                    0
-                });
-            }
-        }
-    });
+fn TIMER2_COMPA() {
+    FADER.tick();
 }
 
-fn get_from_mutex<T: Copy>(mutex: &Mutex<Cell<T>>) -> T {
-    avr_device::interrupt::free(|cs| mutex.borrow(cs).get())
+#[avr_device::interrupt(atmega328p)]
+#[allow(non_snake_case)]
+fn PCINT0() {
+    rotary_change();
 }
 
-fn changed(var: &AtomicBool) -> bool {
-    avr_device::interrupt::free(|_cs| {
-        if var.load(Ordering::SeqCst) {
-            var.store(false, Ordering::SeqCst);
-            true
-        } else {
-            false
-        }
-    })
+#[avr_device::interrupt(atmega328p)]
+#[allow(non_snake_case)]
+fn PCINT2() {
+    rotary_change();
+}
+
+fn rotary_change() {
+    avr_device::interrupt::free(|cs| {
+        ROTARY_PINS.with(cs, |rotary_pins| {
+            let step = DECODER
+                .borrow(cs)
+                .borrow_mut()
+                .update(rotary_pins[1].is_high(), rotary_pins[0].is_high());
+            if step != 0 {
+                if let Some(now) = CLOCK.with(cs, |clock| clock.now()) {
+                    let step = ACCELERATOR.borrow(cs).borrow_mut().step(step, now);
+                    EVENTS.push(Event::Rotated(step)).ok();
+                }
+            }
+        });
+    });
 }
@@ -1,9 +1,9 @@
 #![no_std]
 #![no_main]
 
-use arduino_hal::hal::port::PB5;
-use arduino_hal::port::mode::Output;
-use arduino_hal::port::Pin;
+use nano_common::millis::Millis;
+use nano_common::status::{Pattern, StatusLight};
+use nano_common::time::{Clock, Duration};
 use panic_halt as _;
 
 #[arduino_hal::entry]
@@ -11,24 +11,35 @@ fn main() -> ! {
     let dp = arduino_hal::Peripherals::take().unwrap();
     let pins = arduino_hal::pins!(dp);
 
-    let mut led = pins.d13.into_output();
+    let clock = Millis::init(dp.TC0);
+    let mut status: StatusLight<_, _, 4> =
+        StatusLight::new(pins.d13.into_output(), clock).with_unit(Duration::from_millis(100));
+    unsafe { avr_device::interrupt::enable() };
 
     let mut blinks = 1;
     let limit = 3;
+    // Codes end with 700ms off, which this tops up to the 1.1s they've always had
+    // between them
+    let pause = Duration::from_millis(400);
+    let mut idle_since = None;
 
     loop {
-        blink_n(&mut led, blinks, 100);
-        arduino_hal::delay_ms(1000);
-        blinks += 1;
-        if blinks > limit {
-            blinks -= limit;
+        if status.is_busy() {
+            idle_since = None;
+        } else if clock.now().since(*idle_since.get_or_insert(clock.now())) >= pause {
+            idle_since = None;
+            status
+                .play(Pattern::Blinks {
+                    short: blinks,
+                    long: 0,
+                })
+                .ok();
+            blinks += 1;
+            if blinks > limit {
+                blinks -= limit;
+            }
         }
-    }
-}
-
-fn blink_n(led: &mut Pin<Output, PB5>, times: i32, delay: u16) {
-    for _ in 0..times * 2 { // *2 for toggle on and off
-        led.toggle();
-        arduino_hal::delay_ms(delay);
+        status.update().unwrap();
+        arduino_hal::delay_ms(10);
     }
 }
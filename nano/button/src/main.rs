@@ -2,168 +2,117 @@
 #![no_main]
 #![feature(abi_avr_interrupt)]
 
-use arduino_hal::{
-    delay_ms,
-    hal::port::Dynamic,
-    port::{
-        mode::{Input, PullUp},
-        Pin,
-    },
-    prelude::*,
+use arduino_hal::{delay_ms, prelude::*};
+use nano_common::{
+    button::Button,
+    curve::Curve,
+    dither::MAX_LEVEL,
+    effect::{Effect, EffectPlayer},
+    light::Outputs,
+    millis::Millis,
+    pwm::TickTimer,
+    softpwm::{SoftPwmDriver, SoftPwmPins},
+    time::{Clock, Duration},
 };
-use core::cell;
 use panic_halt as _;
 
-const PRESCALER: u32 = 1024;
-const TIMER_COUNTS: u32 = 125;
+const YELLOW: usize = 0;
+const RED: usize = 1;
+const GREEN: usize = 2;
+const STEPS: u16 = 64;
 
-const MILLIS_INCREMENT: u32 = PRESCALER * TIMER_COUNTS / 16000;
-
-static MILLIS_COUNTER: avr_device::interrupt::Mutex<cell::Cell<u32>> =
-    avr_device::interrupt::Mutex::new(cell::Cell::new(0));
-
-fn millis_init(tc0: arduino_hal::pac::TC0) {
-    // Configure the timer for the above interval (in CTC mode)
-    // and enable its interrupt.
-    tc0.tccr0a.write(|w| w.wgm0().ctc());
-    tc0.ocr0a.write(|w| unsafe { w.bits(TIMER_COUNTS as u8) });
-    tc0.tccr0b.write(|w| match PRESCALER {
-        8 => w.cs0().prescale_8(),
-        64 => w.cs0().prescale_64(),
-        256 => w.cs0().prescale_256(),
-        1024 => w.cs0().prescale_1024(),
-        _ => panic!(),
-    });
-    tc0.timsk0.write(|w| w.ocie0a().set_bit());
-
-    // Reset the global millisecond counter
-    avr_device::interrupt::free(|cs| {
-        MILLIS_COUNTER.borrow(cs).set(0);
-    });
-}
-
-#[avr_device::interrupt(atmega328p)]
-fn TIMER0_COMPA() {
-    avr_device::interrupt::free(|cs| {
-        let counter_cell = MILLIS_COUNTER.borrow(cs);
-        let counter = counter_cell.get();
-        counter_cell.set(counter + MILLIS_INCREMENT);
-    })
-}
-
-fn millis() -> u32 {
-    avr_device::interrupt::free(|cs| MILLIS_COUNTER.borrow(cs).get())
-}
-struct Button<'a> {
-    pin: &'a Pin<Input<PullUp>, Dynamic>,
-    state: bool,
-    last_down: u32,
-    last_up: u32,
-    bump_duration: u32,
-}
-
-impl Button<'_> {
-    fn new(pin: &Pin<Input<PullUp>, Dynamic>) -> Button {
-        Button {
-            pin,
-            state: false,
-            last_down: 0,
-            last_up: 0,
-            bump_duration: u32::MAX,
-        }
-    }
-
-    fn update(&mut self) {
-        if self.pin.is_low() {
-            if !self.state {
-                self.state = true;
-                self.last_down = millis();
-            }
-        } else if self.state {
-            self.state = false;
-            self.last_up = millis();
-
-            self.bump_duration = self.last_up - self.last_down;
-            if self.last_up < self.last_down {
-                self.bump_duration += u32::MAX;
-            }
-        }
-    }
-
-    fn is_pressed(&mut self) -> bool {
-        self.state
-    }
-
-    fn is_held(&self, duration: u32) -> bool {
-        if self.state {
-            let current = millis();
-            let mut diff = current - self.last_down;
-            if current < self.last_down {
-                diff += u32::MAX;
-            }
-            return diff > duration;
-        }
-        false
-    }
-
-    fn was_bumped(&mut self, duration: u32) -> bool {
-        let bumped = self.bump_duration < duration;
-        self.bump_duration = u32::MAX;
-        bumped
-    }
-}
+static SOFT_PWM: SoftPwmDriver<3> = SoftPwmDriver::new(STEPS);
 
 #[arduino_hal::entry]
 fn main() -> ! {
     let peripherals = arduino_hal::Peripherals::take().unwrap();
     let pins = arduino_hal::pins!(peripherals);
     let mut serial = arduino_hal::default_serial!(peripherals, pins, 9600);
-    let mut yellow_led_pin = pins.d9.into_output();
-    let mut red_led_pin = pins.d10.into_output();
-    let mut green_led_pin = pins.d11.into_output();
-    let button_pin = pins.d8.into_pull_up_input().downgrade();
-    let mut button = Button::new(&button_pin);
+    let mut leds = SoftPwmPins::new(
+        &SOFT_PWM,
+        [
+            pins.d9.into_output().into(),
+            pins.d10.into_output().into(),
+            pins.d11.into_output().into(),
+        ],
+    );
+    TickTimer::timer2(peripherals.TC2, SOFT_PWM.tick_rate(100));
 
     // For millis to work
-    millis_init(peripherals.TC0);
+    let clock = Millis::init(peripherals.TC0);
+    let mut button = Button::new(pins.d8.into_pull_up_input().downgrade(), clock);
     unsafe { avr_device::interrupt::enable() };
 
+    // Flickers dimly while waiting, and burns bright while pressed
+    let mut yellow = EffectPlayer::new(0x1234_5678).with_curve(Curve::Cie);
+    yellow.start(
+        Effect::Candle {
+            depth: MAX_LEVEL / 3,
+        },
+        clock.now(),
+    );
+    // Brightens over the second it takes to count as held
+    let mut green = EffectPlayer::new(0).with_curve(Curve::Cie);
+    // Flashes and fades after each short press
+    let mut red = EffectPlayer::new(0).with_curve(Curve::Cie);
+    red.start(
+        Effect::Pulse {
+            decay: Duration::from_millis(1000),
+        },
+        clock.now(),
+    );
+
     ufmt::uwriteln!(&mut serial, "Finished Setup!").void_unwrap();
 
     loop {
-        button.update();
+        let was_pressed = button.is_pressed();
+        button.update().unwrap();
+        let now = clock.now();
 
         // led_pin.toggle();
         // arduino_hal::delay_ms(1000);
         // ufmt::uwriteln!(&mut serial, "Button pressed: {}", button_pin.is_low()).void_unwrap();
-        ufmt::uwriteln!(&mut serial, "Time: {}", millis()).void_unwrap();
+        ufmt::uwriteln!(&mut serial, "Time: {}", clock.now().as_millis()).void_unwrap();
         ufmt::uwriteln!(
             &mut serial,
             "Status: {}, Last Down: {}, Last Up: {}",
-            button.state,
-            button.last_down,
-            button.last_up
+            button.is_pressed(),
+            button.last_down().as_millis(),
+            button.last_up().as_millis()
         )
         .void_unwrap();
 
-        if button.is_pressed() {
-            yellow_led_pin.set_high();
-        } else {
-            yellow_led_pin.set_low();
+        if button.is_pressed() && !was_pressed {
+            green.start(
+                Effect::Sunrise {
+                    duration: Duration::from_millis(1000),
+                },
+                now,
+            );
+        } else if !button.is_pressed() {
+            green.stop();
         }
-
-        if button.is_held(1000) {
-            green_led_pin.set_high();
-        } else {
-            green_led_pin.set_low();
+        if button.was_bumped(Duration::from_millis(1000)) {
+            red.trigger(now);
         }
-
-        if button.was_bumped(1000) {
-            red_led_pin.set_high();
+        yellow.set_brightness(if button.is_pressed() {
+            MAX_LEVEL
         } else {
-            red_led_pin.set_low();
-        }
+            MAX_LEVEL / 2
+        });
+
+        let mut levels = [0; 3];
+        levels[YELLOW] = yellow.level(now);
+        levels[RED] = red.level(now);
+        levels[GREEN] = green.level(now);
+        leds.set_levels(&levels);
 
         delay_ms(50);
     }
 }
+
+#[avr_device::interrupt(atmega328p)]
+#[allow(non_snake_case)]
+fn TIMER2_COMPA() {
+    SOFT_PWM.tick();
+}
@@ -0,0 +1,40 @@
+//! Generates the brightness curve tables for `curve.rs`.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+/// Entries in each table, one per step of an 8-bit input plus one for fully on, so
+/// every input has an entry either side of it to interpolate between.
+const ENTRIES: usize = 257;
+const GAMMA: f64 = 2.2;
+
+fn main() {
+    let out_dir = env::var("OUT_DIR").unwrap();
+    write_table(&out_dir, "gamma.rs", |x| x.powf(GAMMA));
+    write_table(&out_dir, "cie.rs", cie_luminance);
+    println!("cargo:rerun-if-changed=build.rs");
+}
+
+/// Relative luminance that looks `x` of the way to fully on, by CIE 1931 lightness.
+fn cie_luminance(x: f64) -> f64 {
+    let lightness = x * 100.0;
+    if lightness <= 8.0 {
+        lightness / 903.3
+    } else {
+        ((lightness + 16.0) / 116.0).powi(3)
+    }
+}
+
+/// Write `curve` from 0 to 1 as an array expression of 16-bit levels.
+fn write_table(out_dir: &str, name: &str, curve: impl Fn(f64) -> f64) {
+    let last = (ENTRIES - 1) as f64;
+    let levels: Vec<String> = (0..ENTRIES)
+        .map(|i| {
+            let level = (curve(i as f64 / last) * u16::MAX as f64).round();
+            (level as u16).to_string()
+        })
+        .collect();
+    let table = format!("[{}]\n", levels.join(", "));
+    fs::write(Path::new(out_dir).join(name), table).unwrap();
+}
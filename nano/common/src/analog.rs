@@ -0,0 +1,328 @@
+//! Steady readings from a noisy analog input, such as a potentiometer.
+//!
+//! A raw ADC reading of a pot that isn't being touched still wanders by a count or
+//! two, which is enough to make a light flicker between levels. [`AnalogInput`]
+//! averages several readings into one, filters the result, and only reports a new
+//! value once it has moved by more than a dead band.
+//!
+//! A knob that sets more than one thing can go through a [`Pickup`], so that
+//! switching what it sets doesn't make the new setting jump to wherever it is.
+
+use crate::input::{InputEvent, InputSource};
+
+/// Readings the median filter picks from.
+const WINDOW: usize = 5;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Filter {
+    /// Use each reading as it is.
+    None,
+    /// Move `1 / 2^shift` of the way to each new reading. Smooths out noise, at the
+    /// cost of lagging behind a knob that is turned quickly.
+    Exponential { shift: u8 },
+    /// The median of the last five readings. Throws away one-off spikes entirely,
+    /// and lags a couple of readings behind.
+    Median,
+}
+
+/// Filtered readings of one analog input, from 0 to `max`.
+pub struct AnalogInput {
+    id: u8,
+    max: u16,
+    oversampling: u8,
+    filter: Filter,
+    dead_band: u16,
+    /// Exponential filter state, in units of `1 / 2^shift`.
+    average: u32,
+    window: [u16; WINDOW],
+    readings: usize,
+    value: Option<u16>,
+}
+
+impl AnalogInput {
+    /// An input reading up to `max`, 1023 for the ADC, with no filtering at all.
+    pub const fn new(id: u8, max: u16) -> AnalogInput {
+        AnalogInput {
+            id,
+            max,
+            oversampling: 0,
+            filter: Filter::None,
+            dead_band: 0,
+            average: 0,
+            window: [0; WINDOW],
+            readings: 0,
+            value: None,
+        }
+    }
+
+    /// Average `2^shift` samples into each reading.
+    pub fn with_oversampling(mut self, shift: u8) -> Self {
+        self.oversampling = shift.min(16);
+        self
+    }
+
+    pub fn with_filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// How far the filtered reading has to move from the last value reported before
+    /// a new one is. The ends of the range are always reported.
+    pub fn with_dead_band(mut self, dead_band: u16) -> Self {
+        self.dead_band = dead_band;
+        self
+    }
+
+    /// Last value reported, or `None` before the first reading.
+    pub fn value(&self) -> Option<u16> {
+        self.value
+    }
+
+    /// Take a reading with `sample`, returning the new value if it has moved. The
+    /// first reading is always reported.
+    pub fn update(&mut self, mut sample: impl FnMut() -> u16) -> Option<u16> {
+        let samples = 1u32 << self.oversampling;
+        let total: u32 = (0..samples).map(|_| sample().min(self.max) as u32).sum();
+        let reading = ((total + samples / 2) >> self.oversampling) as u16;
+        let filtered = self.filter(reading);
+
+        let moved = match self.value {
+            None => true,
+            Some(value) if filtered == value => false,
+            Some(value) => {
+                filtered == 0
+                    || filtered == self.max
+                    || (filtered as i32 - value as i32).abs() > self.dead_band as i32
+            }
+        };
+        if moved {
+            self.value = Some(filtered);
+            self.value
+        } else {
+            None
+        }
+    }
+
+    /// Source reporting changes of value as [`InputEvent::Analog`], taking a reading
+    /// with `sample` each time it is polled.
+    pub fn reading<F: FnMut() -> u16>(&mut self, sample: F) -> AnalogReading<'_, F> {
+        AnalogReading {
+            input: self,
+            sample,
+        }
+    }
+
+    fn filter(&mut self, reading: u16) -> u16 {
+        let first = self.readings == 0;
+        self.readings = self.readings.saturating_add(1);
+        match self.filter {
+            Filter::None => reading,
+            Filter::Exponential { shift } => {
+                let shift = shift.min(15);
+                let half = 1 << shift >> 1;
+                if first {
+                    self.average = (reading as u32) << shift;
+                } else {
+                    // Rounding what's taken off, rather than truncating it, lets a
+                    // steady input settle right on its value from either side
+                    self.average = self.average - ((self.average + half) >> shift) + reading as u32;
+                }
+                ((self.average + half) >> shift) as u16
+            }
+            Filter::Median => {
+                self.window[(self.readings - 1) % WINDOW] = reading;
+                let mut sorted = self.window;
+                let sorted = &mut sorted[..self.readings.min(WINDOW)];
+                sorted.sort_unstable();
+                sorted[sorted.len() / 2]
+            }
+        }
+    }
+}
+
+/// See [`AnalogInput::reading`].
+pub struct AnalogReading<'a, F> {
+    input: &'a mut AnalogInput,
+    sample: F,
+}
+
+impl<F: FnMut() -> u16> InputSource for AnalogReading<'_, F> {
+    fn poll(&mut self) -> Option<InputEvent> {
+        let value = self.input.update(&mut self.sample)?;
+        Some(InputEvent::Analog {
+            id: self.input.id,
+            value,
+        })
+    }
+}
+
+/// Where a [`Pickup`]'s knob is relative to the setting it controls.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PickupState {
+    /// The knob sets the value.
+    Tracking,
+    /// The knob has no effect until it is turned up to the value.
+    TurnUp,
+    /// The knob has no effect until it is turned down to the value.
+    TurnDown,
+}
+
+/// Soft takeover for a knob shared between several settings.
+///
+/// When the knob is switched to another setting it is unlikely to be where that
+/// setting was left, and taking the knob's position straight away would make the
+/// setting jump. Instead the knob is ignored until it reaches the setting's value,
+/// and only then picks it up.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Pickup {
+    value: u16,
+    state: PickupState,
+}
+
+impl Pickup {
+    /// Already tracking a knob at `value`.
+    pub const fn new(value: u16) -> Pickup {
+        Pickup {
+            value,
+            state: PickupState::Tracking,
+        }
+    }
+
+    /// Hand the knob, at `knob`, over to a setting currently at `value`.
+    pub fn engage(&mut self, value: u16, knob: u16) {
+        self.value = value;
+        self.state = if knob < value {
+            PickupState::TurnUp
+        } else if knob > value {
+            PickupState::TurnDown
+        } else {
+            PickupState::Tracking
+        };
+    }
+
+    pub fn state(&self) -> PickupState {
+        self.state
+    }
+
+    /// Value of the setting, as last picked up from the knob or given to
+    /// [`Pickup::engage`].
+    pub fn value(&self) -> u16 {
+        self.value
+    }
+
+    /// The knob moved to `knob`. Returns the setting's new value if the knob is in
+    /// control of it, including if this move caught up with it.
+    pub fn update(&mut self, knob: u16) -> Option<u16> {
+        let caught_up = match self.state {
+            PickupState::Tracking => true,
+            PickupState::TurnUp => knob >= self.value,
+            PickupState::TurnDown => knob <= self.value,
+        };
+        if !caught_up {
+            return None;
+        }
+        self.state = PickupState::Tracking;
+        self.value = knob;
+        Some(knob)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed `readings` through `input` one at a time, returning every value reported.
+    fn feed(input: &mut AnalogInput, readings: &[u16]) -> Vec<u16> {
+        readings
+            .iter()
+            .filter_map(|&reading| input.update(|| reading))
+            .collect()
+    }
+
+    #[test]
+    fn oversampling_averages_samples() {
+        let mut input = AnalogInput::new(0, 1023).with_oversampling(2);
+        let mut samples = [500, 502, 501, 505, 2000].iter().copied();
+        assert_eq!(input.update(|| samples.next().unwrap()), Some(502));
+        assert_eq!(samples.next(), Some(2000));
+        // Out of range readings are clamped
+        assert_eq!(input.update(|| 2000), Some(1023));
+    }
+
+    #[test]
+    fn filters_smooth_and_remove_spikes() {
+        let mut input = AnalogInput::new(0, 1023).with_filter(Filter::Exponential { shift: 2 });
+        assert_eq!(feed(&mut input, &[0, 400, 400, 400]), [0, 100, 175, 231]);
+
+        let mut input = AnalogInput::new(0, 1023).with_filter(Filter::Median);
+        assert_eq!(feed(&mut input, &[100, 100, 900, 100, 100, 0]), [100]);
+        assert_eq!(feed(&mut input, &[200, 200, 200]), [200]);
+    }
+
+    #[test]
+    fn exponential_filter_settles_on_a_steady_input() {
+        for shift in 1..=15 {
+            let mut input = AnalogInput::new(0, 1023).with_filter(Filter::Exponential { shift });
+            input.update(|| 0);
+            for _ in 0..2_000_000 >> (15 - shift) {
+                input.update(|| 1023);
+            }
+            assert_eq!(input.value(), Some(1023), "shift {}", shift);
+            for _ in 0..2_000_000 >> (15 - shift) {
+                input.update(|| 0);
+            }
+            assert_eq!(input.value(), Some(0), "shift {}", shift);
+        }
+    }
+
+    #[test]
+    fn dead_band_holds_until_moved() {
+        let mut input = AnalogInput::new(0, 1023).with_dead_band(2);
+        assert_eq!(
+            feed(&mut input, &[500, 501, 499, 502, 503, 501, 500]),
+            [500, 503, 500]
+        );
+        assert_eq!(feed(&mut input, &[1022, 1023, 1022, 1]), [1022, 1023, 1]);
+        assert_eq!(feed(&mut input, &[0]), [0]);
+        assert_eq!(input.value(), Some(0));
+    }
+
+    #[test]
+    fn changes_are_input_events() {
+        let mut input = AnalogInput::new(3, 1023).with_dead_band(4);
+        let mut readings = [10, 12, 20].iter().copied();
+        let mut source = input.reading(|| readings.next().unwrap());
+        assert_eq!(source.poll(), Some(InputEvent::Analog { id: 3, value: 10 }));
+        assert_eq!(source.poll(), None);
+        assert_eq!(source.poll(), Some(InputEvent::Analog { id: 3, value: 20 }));
+    }
+
+    #[test]
+    fn pickup_waits_for_the_knob_to_cross() {
+        let mut pickup = Pickup::new(100);
+        assert_eq!(pickup.update(120), Some(120));
+
+        pickup.engage(200, 120);
+        assert_eq!(pickup.state(), PickupState::TurnUp);
+        assert_eq!(pickup.update(150), None);
+        assert_eq!(pickup.update(80), None);
+        assert_eq!(pickup.value(), 200);
+        // Moving past the value in one step still picks it up
+        assert_eq!(pickup.update(205), Some(205));
+        assert_eq!(pickup.state(), PickupState::Tracking);
+        assert_eq!(pickup.update(190), Some(190));
+
+        pickup.engage(50, 190);
+        assert_eq!(pickup.state(), PickupState::TurnDown);
+        assert_eq!(pickup.update(51), None);
+        assert_eq!(pickup.update(50), Some(50));
+    }
+
+    #[test]
+    fn pickup_tracks_a_knob_already_in_place() {
+        let mut pickup = Pickup::new(0);
+        pickup.engage(30, 30);
+        assert_eq!(pickup.state(), PickupState::Tracking);
+        assert_eq!(pickup.update(31), Some(31));
+    }
+}
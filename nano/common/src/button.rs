@@ -0,0 +1,452 @@
+//! Debounced push button and gesture detection on top of it.
+//!
+//! The button is generic over any `embedded_hal` input pin and any [`Clock`], so the
+//! same code runs on the chip with the millis clock and on the host with fakes.
+//! [`GestureDetector`] turns the debounced state into click and long-press events.
+
+use crate::time::{Clock, Duration, Instant};
+use embedded_hal::digital::v2::InputPin;
+
+/// How long the pin has to stay at a new level before it counts.
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(20);
+
+pub struct Button<P, C> {
+    pin: P,
+    clock: C,
+    active_low: bool,
+    debounce: Duration,
+    /// Last level read from the pin, before filtering.
+    raw: bool,
+    /// When `raw` last changed.
+    raw_since: Instant,
+    /// Debounced state.
+    pressed: bool,
+    last_down: Instant,
+    last_up: Instant,
+    bump_duration: Option<Duration>,
+}
+
+impl<P: InputPin, C: Clock> Button<P, C> {
+    /// Button on a pin that is pulled up and shorted to ground when pressed.
+    pub fn new(pin: P, clock: C) -> Button<P, C> {
+        let now = clock.now();
+        Button {
+            pin,
+            clock,
+            active_low: true,
+            debounce: DEFAULT_DEBOUNCE,
+            raw: false,
+            raw_since: now,
+            pressed: false,
+            last_down: now,
+            last_up: now,
+            bump_duration: None,
+        }
+    }
+
+    /// Treat a high pin as pressed instead.
+    pub fn active_high(mut self) -> Self {
+        self.active_low = false;
+        self
+    }
+
+    pub fn with_debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = debounce;
+        self
+    }
+
+    /// Sample the pin. Call this regularly, ideally more often than the debounce time.
+    pub fn update(&mut self) -> Result<(), P::Error> {
+        let now = self.clock.now();
+        let raw = self.pin.is_low()? == self.active_low;
+
+        if raw != self.raw {
+            self.raw = raw;
+            self.raw_since = now;
+        }
+        if raw != self.pressed && now.since(self.raw_since) >= self.debounce {
+            self.pressed = raw;
+            if raw {
+                self.last_down = self.raw_since;
+            } else {
+                self.last_up = self.raw_since;
+                self.bump_duration = Some(self.last_up.since(self.last_down));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_pressed(&self) -> bool {
+        self.pressed
+    }
+
+    /// Whether the button has been down for longer than `duration`.
+    pub fn is_held(&self, duration: Duration) -> bool {
+        self.pressed && self.clock.now().since(self.last_down) > duration
+    }
+
+    /// Whether the last press was shorter than `duration`. Each press is only reported
+    /// once.
+    pub fn was_bumped(&mut self, duration: Duration) -> bool {
+        match self.bump_duration.take() {
+            Some(bump) => bump < duration,
+            None => false,
+        }
+    }
+
+    pub fn last_down(&self) -> Instant {
+        self.last_down
+    }
+
+    pub fn last_up(&self) -> Instant {
+        self.last_up
+    }
+
+    pub(crate) fn now(&self) -> Instant {
+        self.clock.now()
+    }
+}
+
+/// Something the user did with a button.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ButtonEvent {
+    Click,
+    DoubleClick,
+    TripleClick,
+    /// The button has been held for [`GestureTimings::long_press`].
+    LongPressStart,
+    /// Sent every [`GestureTimings::repeat`] while a long press continues.
+    LongPressRepeat,
+    /// A long press has ended. Short presses end in a click instead.
+    Release,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct GestureTimings {
+    /// Longest gap between a release and the next press for them to count towards
+    /// the same multi-click.
+    pub click_gap: Duration,
+    /// How long the button has to be held before it is a long press.
+    pub long_press: Duration,
+    /// Interval between [`ButtonEvent::LongPressRepeat`]s.
+    pub repeat: Duration,
+}
+
+impl Default for GestureTimings {
+    fn default() -> GestureTimings {
+        GestureTimings {
+            click_gap: Duration::from_millis(250),
+            long_press: Duration::from_millis(800),
+            repeat: Duration::from_millis(200),
+        }
+    }
+}
+
+/// Turns a stream of debounced button states into [`ButtonEvent`]s.
+///
+/// Clicks are only reported once [`GestureTimings::click_gap`] has passed without
+/// another press, so a single click arrives that much after the release. A third
+/// click is reported straight away. Holding the button after some clicks turns the
+/// whole sequence into a long press.
+pub struct GestureDetector {
+    timings: GestureTimings,
+    pressed: bool,
+    /// Time of the last press or release.
+    changed_at: Instant,
+    /// Clicks seen in the current sequence, not yet reported.
+    clicks: u8,
+    long: bool,
+    last_repeat: Instant,
+}
+
+impl GestureDetector {
+    pub fn new(timings: GestureTimings) -> GestureDetector {
+        GestureDetector {
+            timings,
+            pressed: false,
+            changed_at: Instant::from_millis(0),
+            clicks: 0,
+            long: false,
+            last_repeat: Instant::from_millis(0),
+        }
+    }
+
+    /// Feed in the current button state. Call this after every [`Button::update`].
+    pub fn update(&mut self, pressed: bool, now: Instant) -> Option<ButtonEvent> {
+        if pressed != self.pressed {
+            self.pressed = pressed;
+            let since_last = now.since(self.changed_at);
+            self.changed_at = now;
+
+            if pressed {
+                // A previous sequence may have timed out without us being polled in between.
+                if self.clicks > 0 && since_last > self.timings.click_gap {
+                    return self.take_clicks();
+                }
+                return None;
+            }
+            if self.long {
+                self.long = false;
+                return Some(ButtonEvent::Release);
+            }
+            self.clicks += 1;
+            if self.clicks == 3 {
+                return self.take_clicks();
+            }
+            return None;
+        }
+
+        let since_last = now.since(self.changed_at);
+        if pressed {
+            if !self.long && since_last >= self.timings.long_press {
+                self.long = true;
+                self.clicks = 0;
+                self.last_repeat = now;
+                return Some(ButtonEvent::LongPressStart);
+            }
+            if self.long && now.since(self.last_repeat) >= self.timings.repeat {
+                self.last_repeat = now;
+                return Some(ButtonEvent::LongPressRepeat);
+            }
+        } else if self.clicks > 0 && since_last > self.timings.click_gap {
+            return self.take_clicks();
+        }
+        None
+    }
+
+    fn take_clicks(&mut self) -> Option<ButtonEvent> {
+        let event = match self.clicks {
+            0 => None,
+            1 => Some(ButtonEvent::Click),
+            2 => Some(ButtonEvent::DoubleClick),
+            _ => Some(ButtonEvent::TripleClick),
+        };
+        self.clicks = 0;
+        event
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+    use core::convert::Infallible;
+
+    struct FakeClock(Cell<u32>);
+
+    impl FakeClock {
+        fn new(millis: u32) -> FakeClock {
+            FakeClock(Cell::new(millis))
+        }
+
+        fn advance(&self, millis: u32) {
+            self.0.set(self.0.get().wrapping_add(millis));
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> Instant {
+            Instant::from_millis(self.0.get())
+        }
+    }
+
+    struct FakePin<'a>(&'a Cell<bool>);
+
+    impl InputPin for FakePin<'_> {
+        type Error = Infallible;
+
+        fn is_high(&self) -> Result<bool, Infallible> {
+            Ok(!self.0.get())
+        }
+
+        fn is_low(&self) -> Result<bool, Infallible> {
+            Ok(self.0.get())
+        }
+    }
+
+    /// Drive the pin to `low` and keep sampling it every millisecond for `millis` ms.
+    fn hold(
+        button: &mut Button<FakePin, &FakeClock>,
+        pin: &Cell<bool>,
+        clock: &FakeClock,
+        low: bool,
+        millis: u32,
+    ) {
+        pin.set(low);
+        for _ in 0..millis {
+            button.update().unwrap();
+            clock.advance(1);
+        }
+    }
+
+    #[test]
+    fn press_after_debounce() {
+        let clock = FakeClock::new(0);
+        let pin = Cell::new(false);
+        let mut button = Button::new(FakePin(&pin), &clock);
+
+        hold(&mut button, &pin, &clock, true, 20);
+        assert!(!button.is_pressed());
+        hold(&mut button, &pin, &clock, true, 1);
+        assert!(button.is_pressed());
+        assert_eq!(button.last_down(), Instant::from_millis(0));
+    }
+
+    #[test]
+    fn bounce_is_ignored() {
+        let clock = FakeClock::new(0);
+        let pin = Cell::new(false);
+        let mut button = Button::new(FakePin(&pin), &clock);
+
+        for _ in 0..10 {
+            hold(&mut button, &pin, &clock, true, 5);
+            hold(&mut button, &pin, &clock, false, 3);
+        }
+        assert!(!button.is_pressed());
+        assert!(!button.was_bumped(Duration::MAX));
+
+        hold(&mut button, &pin, &clock, true, 50);
+        assert!(button.is_pressed());
+        for _ in 0..10 {
+            hold(&mut button, &pin, &clock, false, 3);
+            hold(&mut button, &pin, &clock, true, 5);
+        }
+        assert!(button.is_pressed());
+    }
+
+    #[test]
+    fn active_high() {
+        let clock = FakeClock::new(0);
+        let pin = Cell::new(true);
+        let mut button = Button::new(FakePin(&pin), &clock).active_high();
+
+        hold(&mut button, &pin, &clock, true, 50);
+        assert!(!button.is_pressed());
+        hold(&mut button, &pin, &clock, false, 50);
+        assert!(button.is_pressed());
+    }
+
+    #[test]
+    fn held() {
+        let clock = FakeClock::new(0);
+        let pin = Cell::new(false);
+        let mut button = Button::new(FakePin(&pin), &clock).with_debounce(Duration::ZERO);
+
+        hold(&mut button, &pin, &clock, true, 500);
+        assert!(button.is_held(Duration::from_millis(499)));
+        assert!(!button.is_held(Duration::from_millis(500)));
+        hold(&mut button, &pin, &clock, false, 1);
+        assert!(!button.is_held(Duration::ZERO));
+    }
+
+    #[test]
+    fn bumped_once() {
+        let clock = FakeClock::new(0);
+        let pin = Cell::new(false);
+        let mut button = Button::new(FakePin(&pin), &clock);
+
+        hold(&mut button, &pin, &clock, true, 100);
+        hold(&mut button, &pin, &clock, false, 100);
+        assert!(button.was_bumped(Duration::from_millis(200)));
+        assert!(!button.was_bumped(Duration::from_millis(200)));
+
+        hold(&mut button, &pin, &clock, true, 300);
+        hold(&mut button, &pin, &clock, false, 100);
+        assert!(!button.was_bumped(Duration::from_millis(200)));
+    }
+
+    #[test]
+    fn timing_across_wrap() {
+        let clock = FakeClock::new(u32::MAX - 50);
+        let pin = Cell::new(false);
+        let mut button = Button::new(FakePin(&pin), &clock);
+
+        hold(&mut button, &pin, &clock, true, 100);
+        assert!(button.is_held(Duration::from_millis(50)));
+        hold(&mut button, &pin, &clock, false, 100);
+        assert!(button.was_bumped(Duration::from_millis(150)));
+    }
+
+    /// Run the detector every millisecond, pressing for each `(down, up)` pair in turn,
+    /// then leave the button up for `tail` ms. Returns every event with its time.
+    fn gestures(presses: &[(u32, u32)], tail: u32) -> Vec<(u32, ButtonEvent)> {
+        let mut detector = GestureDetector::new(GestureTimings::default());
+        let mut events = Vec::new();
+        let mut now = 0;
+        let mut run = |pressed: bool, millis: u32, now: &mut u32| {
+            for _ in 0..millis {
+                if let Some(event) = detector.update(pressed, Instant::from_millis(*now)) {
+                    events.push((*now, event));
+                }
+                *now += 1;
+            }
+        };
+        for &(down, up) in presses {
+            run(true, down, &mut now);
+            run(false, up, &mut now);
+        }
+        run(false, tail, &mut now);
+        events
+    }
+
+    #[test]
+    fn single_click_after_gap() {
+        let events = gestures(&[(100, 0)], 1000);
+        assert_eq!(events, vec![(351, ButtonEvent::Click)]);
+    }
+
+    #[test]
+    fn double_and_triple_click() {
+        let events = gestures(&[(100, 100), (100, 0)], 1000);
+        assert_eq!(events, vec![(551, ButtonEvent::DoubleClick)]);
+
+        let events = gestures(&[(100, 100), (100, 100), (100, 0)], 1000);
+        assert_eq!(events, vec![(500, ButtonEvent::TripleClick)]);
+    }
+
+    #[test]
+    fn slow_clicks_are_separate() {
+        let events = gestures(&[(100, 400), (100, 0)], 1000);
+        assert_eq!(
+            events,
+            vec![(351, ButtonEvent::Click), (851, ButtonEvent::Click)]
+        );
+    }
+
+    #[test]
+    fn long_press_repeats_then_releases() {
+        let events = gestures(&[(1250, 0)], 1000);
+        assert_eq!(
+            events,
+            vec![
+                (800, ButtonEvent::LongPressStart),
+                (1000, ButtonEvent::LongPressRepeat),
+                (1200, ButtonEvent::LongPressRepeat),
+                (1250, ButtonEvent::Release),
+            ]
+        );
+    }
+
+    #[test]
+    fn click_then_hold_is_long_press() {
+        let events = gestures(&[(100, 100), (900, 0)], 1000);
+        assert_eq!(
+            events,
+            vec![
+                (1000, ButtonEvent::LongPressStart),
+                (1100, ButtonEvent::Release)
+            ]
+        );
+    }
+
+    #[test]
+    fn stale_click_reported_on_next_press() {
+        let mut detector = GestureDetector::new(GestureTimings::default());
+        let at = Instant::from_millis;
+        assert_eq!(detector.update(true, at(0)), None);
+        assert_eq!(detector.update(false, at(100)), None);
+        assert_eq!(detector.update(true, at(1000)), Some(ButtonEvent::Click));
+        assert_eq!(detector.update(false, at(1100)), None);
+        assert_eq!(detector.update(false, at(2000)), Some(ButtonEvent::Click));
+    }
+}
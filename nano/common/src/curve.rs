@@ -0,0 +1,116 @@
+//! Brightness curves, to make equal steps of a knob look like equal steps of light.
+//!
+//! The eye is far more sensitive to changes in dim light than in bright light, so a
+//! duty that goes up in a straight line looks like it does most of its brightening
+//! at the bottom of the range. A [`Curve`] maps a perceived brightness to the light
+//! output that gives it.
+//!
+//! The curves are tabulated by `build.rs` as 16-bit levels, and kept in flash on the
+//! chip as they would otherwise take up a good part of its RAM. Inputs between
+//! entries are interpolated, and the result is scaled to whatever resolution the
+//! output has.
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Curve {
+    /// Light output in proportion to the input.
+    Linear,
+    /// Light output of the input to the power of 2.2.
+    Gamma,
+    /// Light output giving the input as CIE 1931 lightness.
+    Cie,
+}
+
+/// Entries in each table.
+const ENTRIES: usize = 257;
+
+#[cfg(target_arch = "avr")]
+avr_progmem::progmem! {
+    static progmem GAMMA: [u16; ENTRIES] = include!(concat!(env!("OUT_DIR"), "/gamma.rs"));
+    static progmem CIE: [u16; ENTRIES] = include!(concat!(env!("OUT_DIR"), "/cie.rs"));
+}
+
+#[cfg(not(target_arch = "avr"))]
+static GAMMA: [u16; ENTRIES] = include!(concat!(env!("OUT_DIR"), "/gamma.rs"));
+#[cfg(not(target_arch = "avr"))]
+static CIE: [u16; ENTRIES] = include!(concat!(env!("OUT_DIR"), "/cie.rs"));
+
+impl Curve {
+    /// Light output, out of `u16::MAX`, for a brightness of `value` out of `max`.
+    pub fn level(self, value: u32, max: u32) -> u16 {
+        let input = crate::dither::level(value, max) as u32;
+        if self == Curve::Linear {
+            return input as u16;
+        }
+        // Stretch to 0..=65536 so that fully on lands exactly on the last entry
+        let input = input + (input >> 15);
+        let index = (input >> 8) as usize;
+        let low = self.entry(index) as u32;
+        if index == ENTRIES - 1 {
+            return low as u16;
+        }
+        let high = self.entry(index + 1) as u32;
+        let fraction = input & 0xff;
+        (low + ((high - low) * fraction + 0x80) / 0x100) as u16
+    }
+
+    /// [`Curve::level`] as a duty out of `top`.
+    pub fn duty(self, value: u32, max: u32, top: u16) -> u16 {
+        let level = self.level(value, max) as u32;
+        ((level * top as u32 + u16::MAX as u32 / 2) / u16::MAX as u32) as u16
+    }
+
+    #[cfg(target_arch = "avr")]
+    fn entry(self, index: usize) -> u16 {
+        match self {
+            Curve::Linear => unreachable!(),
+            Curve::Gamma => GAMMA.load_at(index),
+            Curve::Cie => CIE.load_at(index),
+        }
+    }
+
+    #[cfg(not(target_arch = "avr"))]
+    fn entry(self, index: usize) -> u16 {
+        match self {
+            Curve::Linear => unreachable!(),
+            Curve::Gamma => GAMMA[index],
+            Curve::Cie => CIE[index],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CURVES: [Curve; 3] = [Curve::Linear, Curve::Gamma, Curve::Cie];
+
+    #[test]
+    fn ends_are_off_and_fully_on() {
+        for &curve in CURVES.iter() {
+            assert_eq!(curve.level(0, 1023), 0);
+            assert_eq!(curve.level(1023, 1023), u16::MAX);
+            assert_eq!(curve.duty(0, 1023, 255), 0);
+            assert_eq!(curve.duty(1023, 1023, 255), 255);
+            assert_eq!(curve.duty(1023, 1023, 1023), 1023);
+        }
+    }
+
+    #[test]
+    fn curves_rise_steadily() {
+        for &curve in CURVES.iter() {
+            let levels: Vec<u16> = (0..=1023).map(|value| curve.level(value, 1023)).collect();
+            assert!(levels.windows(2).all(|pair| pair[0] <= pair[1]));
+        }
+    }
+
+    #[test]
+    fn curves_are_dim_halfway() {
+        assert_eq!(Curve::Linear.duty(512, 1023, 1023), 512);
+        // About a fifth of the light for each
+        assert!((200..240).contains(&Curve::Gamma.duty(512, 1023, 1023)));
+        assert!((175..200).contains(&Curve::Cie.duty(512, 1023, 1023)));
+        // The bottom of a 10-bit knob still lights a 10-bit output
+        assert_eq!(Curve::Cie.duty(8, 1023, 1023), 1);
+        assert_eq!(Curve::Cie.duty(8, 1023, 255), 0);
+    }
+}
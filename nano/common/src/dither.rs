@@ -0,0 +1,116 @@
+//! Finer steps of brightness than a timer's resolution, by alternating between
+//! neighbouring duties from one period to the next.
+//!
+//! A level is a 16-bit fraction of fully on. Most levels fall between two duties the
+//! timer can produce, so the timer's overflow interrupt calls [`DitherDriver::tick`]
+//! once a period to pick one of them, choosing the higher one just often enough that
+//! the average comes out at the level. At the frequencies the timers run at the eye
+//! only sees the average.
+//!
+//! This matters most at the bottom of the range, where a single step of an 8-bit
+//! timer is already a visible jump.
+
+#[cfg(target_arch = "avr")]
+mod driver;
+
+#[cfg(target_arch = "avr")]
+pub use driver::{DitherDriver, DitheredChannel, LevelWriter};
+
+/// Fully on.
+pub const MAX_LEVEL: u16 = u16::MAX;
+
+/// `value` out of `max` as a level.
+pub fn level(value: u32, max: u32) -> u16 {
+    let value = value.min(max) as u64 * MAX_LEVEL as u64 / max.max(1) as u64;
+    value as u16
+}
+
+/// The sequence of duties for one output.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Dither {
+    top: u16,
+    level: u16,
+    duty: u16,
+    /// How far the level is above `duty`, in 65536ths of a step.
+    fraction: u16,
+    error: u16,
+}
+
+impl Dither {
+    /// Off, for a timer whose duties go up to `top`.
+    pub const fn new(top: u16) -> Dither {
+        Dither {
+            top,
+            level: 0,
+            duty: 0,
+            fraction: 0,
+            error: 0,
+        }
+    }
+
+    pub fn level(&self) -> u16 {
+        self.level
+    }
+
+    pub fn set_level(&mut self, level: u16) {
+        // Stretch 0..=65535 to 0..=65536 so that the top level is exactly `top`
+        let scaled = (level as u32 + (level as u32 >> 15)) * self.top as u32;
+        self.level = level;
+        self.duty = (scaled >> 16) as u16;
+        self.fraction = scaled as u16;
+    }
+
+    /// Duty for the next period.
+    pub fn next_duty(&mut self) -> u16 {
+        let (error, carry) = self.error.overflowing_add(self.fraction);
+        self.error = error;
+        self.duty + carry as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn duties(dither: &mut Dither, periods: usize) -> Vec<u16> {
+        (0..periods).map(|_| dither.next_duty()).collect()
+    }
+
+    #[test]
+    fn ends_of_the_range_are_steady() {
+        let mut dither = Dither::new(255);
+        assert!(duties(&mut dither, 16).iter().all(|&duty| duty == 0));
+        dither.set_level(MAX_LEVEL);
+        assert!(duties(&mut dither, 16).iter().all(|&duty| duty == 255));
+    }
+
+    #[test]
+    fn average_matches_level() {
+        let mut dither = Dither::new(255);
+        // A quarter of the way from 1 to 2
+        dither.set_level(level(5, 4 * 255));
+        let duties = duties(&mut dither, 256);
+        assert!(duties.iter().all(|&duty| duty == 1 || duty == 2));
+        let higher = duties.iter().filter(|&&duty| duty == 2).count();
+        assert!((63..=64).contains(&higher));
+    }
+
+    #[test]
+    fn higher_duties_are_spread_out() {
+        let mut dither = Dither::new(1023);
+        // Just under half way from 0 to 1
+        dither.set_level(level(1, 2 * 1023));
+        let duties = duties(&mut dither, 64);
+        assert_eq!(duties.iter().filter(|&&duty| duty == 1).count(), 31);
+        assert!(duties.windows(2).all(|pair| pair != [1, 1]));
+        assert!(duties.windows(3).all(|run| run != [0, 0, 0]));
+    }
+
+    #[test]
+    fn level_rescales() {
+        assert_eq!(level(0, 1023), 0);
+        assert_eq!(level(1023, 1023), MAX_LEVEL);
+        assert_eq!(level(2000, 1023), MAX_LEVEL);
+        assert_eq!(level(1, 0), 0);
+    }
+}
@@ -0,0 +1,145 @@
+//! Dithering a timer's outputs from its overflow interrupt.
+
+use super::Dither;
+use crate::curve::Curve;
+use crate::light::LightOutput;
+use crate::pwm::{Output, PwmChannel, PwmTimer};
+use avr_device::interrupt::{self, Mutex};
+use core::cell::RefCell;
+use core::marker::PhantomData;
+
+/// Dithering state for both outputs of a timer, shared with its overflow interrupt.
+/// Meant to live in a `static`.
+pub struct DitherDriver<T> {
+    outputs: Mutex<RefCell<[Option<Dither>; 2]>>,
+    timer: PhantomData<fn() -> T>,
+}
+
+impl<T: PwmTimer> DitherDriver<T> {
+    pub const fn new() -> DitherDriver<T> {
+        DitherDriver {
+            outputs: Mutex::new(RefCell::new([None, None])),
+            timer: PhantomData,
+        }
+    }
+
+    /// Take over setting `channel`'s duty, starting at a level of 0, and enable the
+    /// timer's overflow interrupt, which has to call [`DitherDriver::tick`].
+    pub fn attach<'t>(&'static self, channel: PwmChannel<'t, T>) -> DitheredChannel<'t, T> {
+        let timer = channel.timer();
+        let dither = Dither::new(timer.period().top);
+        interrupt::free(|cs| {
+            self.outputs.borrow(cs).borrow_mut()[index(channel.output())] = Some(dither);
+        });
+        timer.enable_overflow_interrupt();
+        DitheredChannel {
+            driver: self,
+            channel,
+            curve: Curve::Linear,
+        }
+    }
+
+    /// Load the duties for the next period. Call from the timer's overflow interrupt.
+    pub fn tick(&self) {
+        interrupt::free(|cs| {
+            let mut outputs = self.outputs.borrow(cs).borrow_mut();
+            for (dither, &output) in outputs.iter_mut().zip([Output::A, Output::B].iter()) {
+                if let Some(dither) = dither {
+                    // Safety: the channel's own writes all go through here
+                    unsafe { T::write_compare(output, dither.next_duty()) };
+                }
+            }
+        });
+    }
+
+    fn with<R>(&self, output: Output, f: impl FnOnce(&mut Dither) -> R) -> R {
+        interrupt::free(|cs| {
+            let mut outputs = self.outputs.borrow(cs).borrow_mut();
+            f(outputs[index(output)].as_mut().unwrap())
+        })
+    }
+}
+
+impl<T: PwmTimer> Default for DitherDriver<T> {
+    fn default() -> DitherDriver<T> {
+        DitherDriver::new()
+    }
+}
+
+fn index(output: Output) -> usize {
+    match output {
+        Output::A => 0,
+        Output::B => 1,
+    }
+}
+
+/// A PWM channel set by 16-bit level rather than duty.
+pub struct DitheredChannel<'t, T: PwmTimer> {
+    driver: &'static DitherDriver<T>,
+    channel: PwmChannel<'t, T>,
+    curve: Curve,
+}
+
+impl<'t, T: PwmTimer> DitheredChannel<'t, T> {
+    pub fn enable(&mut self) {
+        self.channel.enable();
+    }
+
+    pub fn disable(&mut self) {
+        self.channel.disable();
+    }
+
+    pub fn level(&self) -> u16 {
+        self.driver
+            .with(self.channel.output(), |dither| dither.level())
+    }
+
+    /// Set the level, out of [`MAX_LEVEL`](super::MAX_LEVEL), from the next period on.
+    pub fn set_level(&mut self, level: u16) {
+        self.driver
+            .with(self.channel.output(), |dither| dither.set_level(level));
+    }
+
+    /// A handle for an interrupt handler to set the level with.
+    pub fn level_writer(&self) -> LevelWriter<T> {
+        LevelWriter {
+            driver: self.driver,
+            output: self.channel.output(),
+        }
+    }
+
+    pub fn curve(&self) -> Curve {
+        self.curve
+    }
+
+    /// Curve used by [`set_brightness`](DitheredChannel::set_brightness).
+    pub fn set_curve(&mut self, curve: Curve) {
+        self.curve = curve;
+    }
+
+    /// Set the level for a perceived brightness of `value` out of `max`. Takes a
+    /// wider range than [`PwmChannel::set_brightness`], to make use of the extra
+    /// resolution.
+    pub fn set_brightness(&mut self, value: u32, max: u32) {
+        self.set_level(self.curve.level(value, max));
+    }
+}
+
+impl<T: PwmTimer> LightOutput for DitheredChannel<'_, T> {
+    fn set_level(&mut self, level: u16) {
+        DitheredChannel::set_level(self, level)
+    }
+}
+
+/// Sets a [`DitheredChannel`]'s level without borrowing the channel.
+pub struct LevelWriter<T: PwmTimer> {
+    driver: &'static DitherDriver<T>,
+    output: Output,
+}
+
+impl<T: PwmTimer> LightOutput for LevelWriter<T> {
+    fn set_level(&mut self, level: u16) {
+        self.driver
+            .with(self.output, |dither| dither.set_level(level));
+    }
+}
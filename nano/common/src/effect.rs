@@ -0,0 +1,293 @@
+//! Lighting effects that play on a single channel.
+//!
+//! An [`Effect`] describes how a channel's level moves over time. An
+//! [`EffectPlayer`] plays one, working out the level for any instant it's asked
+//! about, so it can be called from the main loop at whatever rate suits and still
+//! keep time. The level is scaled by the player's brightness and mapped through its
+//! [`Curve`], and can go to any [`LightOutput`].
+
+use crate::curve::Curve;
+use crate::dither::{self, MAX_LEVEL};
+use crate::fade::Easing;
+use crate::light::LightOutput;
+use crate::time::{Duration, Instant};
+
+/// Time between changes of a candle's flame.
+const FLICKER_STEP: Duration = Duration::from_millis(50);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Effect {
+    /// Rises and falls smoothly, once every `period`.
+    Breathe { period: Duration },
+    /// Flickers like a flame, dipping by up to `depth` out of [`MAX_LEVEL`].
+    Candle { depth: u16 },
+    /// Fully on for `on`, then off for `off`.
+    Strobe { on: Duration, off: Duration },
+    /// Off until [`EffectPlayer::trigger`]ed, then jumps to fully on and dies away
+    /// over `decay`.
+    Pulse { decay: Duration },
+    /// Rises from off to fully on over `duration`, and stays on.
+    Sunrise { duration: Duration },
+}
+
+/// Xorshift pseudo-random numbers. Nowhere near good enough for anything that
+/// matters, but plenty for flickering a light.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Rng(u32);
+
+impl Rng {
+    /// Any seed will do, though the same seed always gives the same numbers.
+    pub const fn new(seed: u32) -> Rng {
+        // An all-zero state would only ever give zeroes
+        Rng(if seed == 0 { 0x9e37_79b9 } else { seed })
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.0 = x;
+        x
+    }
+
+    /// A number from 0 up to and including `max`.
+    pub fn up_to(&mut self, max: u16) -> u16 {
+        (self.next_u32() % (max as u32 + 1)) as u16
+    }
+}
+
+/// Plays an [`Effect`] on one channel.
+pub struct EffectPlayer {
+    effect: Option<Effect>,
+    started: Instant,
+    triggered: bool,
+    brightness: u16,
+    curve: Curve,
+    rng: Rng,
+    flame: u16,
+    next_flicker: Instant,
+    level: Option<u16>,
+}
+
+impl EffectPlayer {
+    /// Stopped, at full brightness. `seed` starts the candle flicker.
+    pub const fn new(seed: u32) -> EffectPlayer {
+        EffectPlayer {
+            effect: None,
+            started: Instant::from_millis(0),
+            triggered: false,
+            brightness: MAX_LEVEL,
+            curve: Curve::Linear,
+            rng: Rng::new(seed),
+            flame: MAX_LEVEL,
+            next_flicker: Instant::from_millis(0),
+            level: None,
+        }
+    }
+
+    /// Curve the effect's level is mapped through. Defaults to [`Curve::Linear`].
+    pub fn with_curve(mut self, curve: Curve) -> Self {
+        self.curve = curve;
+        self
+    }
+
+    /// Play `effect` from the start, in place of whatever was playing.
+    pub fn start(&mut self, effect: Effect, now: Instant) {
+        self.effect = Some(effect);
+        self.started = now;
+        self.triggered = false;
+        self.flame = MAX_LEVEL;
+        self.next_flicker = now;
+    }
+
+    /// Stop playing, which turns the channel off.
+    pub fn stop(&mut self) {
+        self.effect = None;
+    }
+
+    pub fn effect(&self) -> Option<Effect> {
+        self.effect
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.effect.is_some()
+    }
+
+    /// Fire an [`Effect::Pulse`], or restart any other effect from the beginning.
+    pub fn trigger(&mut self, now: Instant) {
+        self.started = now;
+        self.triggered = true;
+    }
+
+    /// What the effect is scaled by, out of [`MAX_LEVEL`].
+    pub fn brightness(&self) -> u16 {
+        self.brightness
+    }
+
+    pub fn set_brightness(&mut self, brightness: u16) {
+        self.brightness = brightness;
+    }
+
+    /// Light output at `now`, out of [`MAX_LEVEL`].
+    pub fn level(&mut self, now: Instant) -> u16 {
+        let elapsed = now.since(self.started).as_millis();
+        let level = match self.effect {
+            None => 0,
+            Some(Effect::Breathe { period }) => {
+                let period = period.as_millis().max(1);
+                let phase = dither::level(elapsed % period, period) as u32;
+                // Up for the first half of the period and down for the second
+                let rise = (2 * phase.min(MAX_LEVEL as u32 - phase)).min(MAX_LEVEL as u32);
+                Easing::InOut.apply(rise as u16)
+            }
+            Some(Effect::Candle { depth }) => {
+                if !now.is_before(self.next_flicker) {
+                    let target = MAX_LEVEL - self.rng.up_to(depth);
+                    self.flame = ((self.flame as u32 + target as u32) / 2) as u16;
+                    self.next_flicker = now + FLICKER_STEP;
+                }
+                self.flame
+            }
+            Some(Effect::Strobe { on, off }) => {
+                let cycle = (on + off).as_millis().max(1);
+                if elapsed % cycle < on.as_millis() {
+                    MAX_LEVEL
+                } else {
+                    0
+                }
+            }
+            Some(Effect::Pulse { decay }) => {
+                if !self.triggered || elapsed >= decay.as_millis() {
+                    0
+                } else {
+                    let progress = dither::level(elapsed, decay.as_millis());
+                    MAX_LEVEL - Easing::Out.apply(progress)
+                }
+            }
+            Some(Effect::Sunrise { duration }) => {
+                if elapsed >= duration.as_millis() {
+                    MAX_LEVEL
+                } else {
+                    dither::level(elapsed, duration.as_millis())
+                }
+            }
+        };
+        let scaled = level as u32 * self.brightness as u32 / MAX_LEVEL as u32;
+        self.curve.level(scaled, MAX_LEVEL as u32)
+    }
+
+    /// Write the level at `now` to `output`, if it's changed since the last write.
+    pub fn run<O: LightOutput>(&mut self, output: &mut O, now: Instant) {
+        let level = self.level(now);
+        if self.level != Some(level) {
+            self.level = Some(level);
+            output.set_level(level);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(millis: u32) -> Instant {
+        Instant::from_millis(millis)
+    }
+
+    #[test]
+    fn periodic_effects_repeat() {
+        let mut player = EffectPlayer::new(1);
+        player.start(
+            Effect::Breathe {
+                period: Duration::from_millis(1000),
+            },
+            at(0),
+        );
+        assert_eq!(player.level(at(0)), 0);
+        assert_eq!(player.level(at(500)), MAX_LEVEL);
+        assert!(player.level(at(250)) > player.level(at(100)));
+        assert!(player.level(at(750)) < player.level(at(600)));
+        assert_eq!(player.level(at(1250)), player.level(at(250)));
+
+        player.start(
+            Effect::Strobe {
+                on: Duration::from_millis(50),
+                off: Duration::from_millis(150),
+            },
+            at(1000),
+        );
+        let levels: Vec<u16> = (1000..1400)
+            .step_by(50)
+            .map(|t| player.level(at(t)))
+            .collect();
+        assert_eq!(levels, [MAX_LEVEL, 0, 0, 0, MAX_LEVEL, 0, 0, 0]);
+    }
+
+    #[test]
+    fn pulse_waits_for_a_trigger() {
+        let mut player = EffectPlayer::new(1);
+        player.start(
+            Effect::Pulse {
+                decay: Duration::from_millis(100),
+            },
+            at(0),
+        );
+        assert_eq!(player.level(at(10)), 0);
+        player.trigger(at(20));
+        assert_eq!(player.level(at(20)), MAX_LEVEL);
+        assert!(player.level(at(70)) < MAX_LEVEL / 2);
+        assert!(player.level(at(100)) > 0);
+        assert_eq!(player.level(at(120)), 0);
+
+        player.start(
+            Effect::Sunrise {
+                duration: Duration::from_secs(10),
+            },
+            at(0),
+        );
+        assert_eq!(player.level(at(0)), 0);
+        assert_eq!(player.level(at(5000)), MAX_LEVEL / 2);
+        assert_eq!(player.level(at(60_000)), MAX_LEVEL);
+    }
+
+    #[test]
+    fn candle_flickers_within_its_depth() {
+        let mut player = EffectPlayer::new(1);
+        let depth = MAX_LEVEL / 4;
+        player.start(Effect::Candle { depth }, at(0));
+        let levels: Vec<u16> = (0..200).map(|step| player.level(at(step * 50))).collect();
+        assert!(levels.iter().all(|&level| level >= MAX_LEVEL - depth));
+        assert!(levels.windows(2).filter(|pair| pair[0] != pair[1]).count() > 150);
+        // Nothing changes between steps
+        assert_eq!(player.level(at(9975)), levels[199]);
+    }
+
+    #[test]
+    fn brightness_scales_and_stop_turns_off() {
+        #[derive(Default)]
+        struct Recorded(Vec<u16>);
+
+        impl LightOutput for Recorded {
+            fn set_level(&mut self, level: u16) {
+                self.0.push(level);
+            }
+        }
+
+        let mut output = Recorded::default();
+        let mut player = EffectPlayer::new(1);
+        player.start(
+            Effect::Sunrise {
+                duration: Duration::ZERO,
+            },
+            at(0),
+        );
+        player.set_brightness(MAX_LEVEL / 2);
+        player.run(&mut output, at(0));
+        player.run(&mut output, at(10));
+        player.stop();
+        player.run(&mut output, at(20));
+        assert_eq!(output.0, [MAX_LEVEL / 2, 0]);
+        assert!(!player.is_running());
+    }
+}
@@ -0,0 +1,332 @@
+//! Quadrature decoding for rotary encoders.
+//!
+//! Both channels are fed into a Gray-code transition table on every edge. Valid
+//! transitions move a sub-detent counter one count either way, contact bounce on one
+//! channel cancels itself out, and transitions where both channels changed at once
+//! are rejected because the direction can't be known.
+//!
+//! [`Accelerator`] sits on top of the decoder and turns each detent into a step size
+//! that grows the faster the knob is spun.
+
+use crate::time::{Duration, Instant};
+
+/// How many quadrature counts one click of the encoder produces.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CountsPerDetent {
+    One,
+    Two,
+    Four,
+}
+
+impl CountsPerDetent {
+    const fn counts(self) -> i8 {
+        match self {
+            CountsPerDetent::One => 1,
+            CountsPerDetent::Two => 2,
+            CountsPerDetent::Four => 4,
+        }
+    }
+}
+
+/// Marks a transition where both channels changed.
+const ILLEGAL: i8 = 2;
+
+/// Count for each `previous << 2 | current` pair of `a << 1 | b` states.
+#[rustfmt::skip]
+const TRANSITIONS: [i8; 16] = [
+    // current: 00    01       10       11
+    /* 00 */    0,    1,       -1,      ILLEGAL,
+    /* 01 */    -1,   0,       ILLEGAL, 1,
+    /* 10 */    1,    ILLEGAL, 0,       -1,
+    /* 11 */    ILLEGAL, -1,   1,       0,
+];
+
+#[derive(Clone, Copy, Debug)]
+pub struct QuadratureDecoder {
+    counts_per_detent: CountsPerDetent,
+    state: u8,
+    counts: i8,
+    illegal: u16,
+}
+
+impl QuadratureDecoder {
+    /// Start with both channels high, which is where a pulled-up encoder rests.
+    pub const fn new(counts_per_detent: CountsPerDetent) -> QuadratureDecoder {
+        QuadratureDecoder {
+            counts_per_detent,
+            state: 0b11,
+            counts: 0,
+            illegal: 0,
+        }
+    }
+
+    /// Feed in the current level of both channels, typically from a pin change
+    /// interrupt on either of them.
+    ///
+    /// Returns 1 or -1 when a full detent has been turned and 0 otherwise. A falling
+    /// edge on `a` while `b` is low counts up.
+    pub fn update(&mut self, a: bool, b: bool) -> i8 {
+        let current = (a as u8) << 1 | b as u8;
+        let count = TRANSITIONS[(self.state << 2 | current) as usize];
+        self.state = current;
+
+        if count == ILLEGAL {
+            self.illegal = self.illegal.wrapping_add(1);
+            return 0;
+        }
+        self.counts += count;
+
+        let per_detent = self.counts_per_detent.counts();
+        if self.counts >= per_detent {
+            self.counts -= per_detent;
+            1
+        } else if self.counts <= -per_detent {
+            self.counts += per_detent;
+            -1
+        } else {
+            0
+        }
+    }
+
+    /// Number of rejected transitions so far. A steadily climbing value means edges
+    /// are being missed.
+    pub fn illegal_transitions(&self) -> u16 {
+        self.illegal
+    }
+}
+
+/// How the step size grows with turning speed.
+///
+/// Detents further apart than `slow` step by `min_step`, detents closer together than
+/// `fast` step by `max_step`, and anything in between is interpolated linearly. A
+/// `max_step` below `min_step` is taken as `min_step`. [`Accelerator::step`] never
+/// steps by more than `i16::MAX`.
+#[derive(Clone, Copy, Debug)]
+pub struct AccelerationCurve {
+    pub slow: Duration,
+    pub fast: Duration,
+    pub min_step: u16,
+    pub max_step: u16,
+}
+
+impl AccelerationCurve {
+    pub fn step_for(&self, interval: Duration) -> u16 {
+        let max_step = self.max_step.max(self.min_step);
+        if interval >= self.slow {
+            return self.min_step;
+        }
+        if interval <= self.fast {
+            return max_step;
+        }
+        let span = self.slow.saturating_sub(self.fast).as_millis();
+        let into = self.slow.saturating_sub(interval).as_millis();
+        let extra = (max_step - self.min_step) as u32 * into / span;
+        self.min_step + extra as u16
+    }
+}
+
+/// Picks a step size for each detent from the time since the previous one.
+///
+/// The interval is smoothed over a few detents so a single quick flick doesn't jump
+/// straight to the largest step, and reversing direction starts again from slow.
+pub struct Accelerator {
+    curve: AccelerationCurve,
+    last: Option<Instant>,
+    direction: i8,
+    interval: Duration,
+}
+
+impl Accelerator {
+    pub const fn new(curve: AccelerationCurve) -> Accelerator {
+        Accelerator {
+            curve,
+            last: None,
+            direction: 0,
+            interval: curve.slow,
+        }
+    }
+
+    /// Signed step for a detent in `direction` (as returned by
+    /// [`QuadratureDecoder::update`]) turned at `now`.
+    pub fn step(&mut self, direction: i8, now: Instant) -> i16 {
+        let interval = match self.last {
+            Some(last) if direction == self.direction => now.since(last).min(self.curve.slow),
+            _ => self.curve.slow,
+        };
+        self.interval = if direction == self.direction {
+            Duration::from_millis((self.interval.as_millis() * 3 + interval.as_millis()) / 4)
+        } else {
+            interval
+        };
+        self.last = Some(now);
+        self.direction = direction;
+
+        let step = self.curve.step_for(self.interval).min(i16::MAX as u16) as i16;
+        if direction < 0 {
+            -step
+        } else {
+            step
+        }
+    }
+
+    /// Current turning speed in detents per second, 0 once the knob has been still for
+    /// longer than the curve's `slow` interval.
+    pub fn velocity(&self, now: Instant) -> u16 {
+        match self.last {
+            Some(last) if now.since(last) <= self.curve.slow => {
+                (1000 / self.interval.as_millis().max(1)) as u16
+            }
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One detent's worth of `(a, b)` readings counting up, starting from rest.
+    const UP: [(bool, bool); 4] = [(true, false), (false, false), (false, true), (true, true)];
+
+    fn feed(decoder: &mut QuadratureDecoder, states: &[(bool, bool)]) -> Vec<i8> {
+        states
+            .iter()
+            .map(|&(a, b)| decoder.update(a, b))
+            .filter(|&step| step != 0)
+            .collect()
+    }
+
+    fn down() -> Vec<(bool, bool)> {
+        let mut states: Vec<_> = UP.iter().rev().skip(1).copied().collect();
+        states.push((true, true));
+        states
+    }
+
+    #[test]
+    fn full_step_encoder() {
+        let mut decoder = QuadratureDecoder::new(CountsPerDetent::Four);
+        assert_eq!(feed(&mut decoder, &UP), vec![1]);
+        assert_eq!(feed(&mut decoder, &UP), vec![1]);
+        assert_eq!(feed(&mut decoder, &down()), vec![-1]);
+        assert_eq!(decoder.illegal_transitions(), 0);
+    }
+
+    #[test]
+    fn half_step_encoder() {
+        let mut decoder = QuadratureDecoder::new(CountsPerDetent::Two);
+        assert_eq!(feed(&mut decoder, &UP), vec![1, 1]);
+        assert_eq!(feed(&mut decoder, &down()), vec![-1, -1]);
+    }
+
+    #[test]
+    fn quarter_step_encoder() {
+        let mut decoder = QuadratureDecoder::new(CountsPerDetent::One);
+        assert_eq!(feed(&mut decoder, &UP), vec![1, 1, 1, 1]);
+        assert_eq!(feed(&mut decoder, &down()), vec![-1, -1, -1, -1]);
+    }
+
+    #[test]
+    fn bounce_is_not_movement() {
+        let mut decoder = QuadratureDecoder::new(CountsPerDetent::Four);
+        // `a` chatters as it falls, then `b` chatters as the turn is reversed.
+        let recorded = [
+            (false, true),
+            (true, true),
+            (false, true),
+            (true, true),
+            (false, true),
+            (false, false),
+            (false, true),
+            (false, false),
+            (false, true),
+            (true, true),
+        ];
+        assert_eq!(feed(&mut decoder, &recorded), vec![]);
+        assert_eq!(decoder.illegal_transitions(), 0);
+
+        let bouncy_turn = [
+            (true, false),
+            (true, true),
+            (true, false),
+            (false, false),
+            (true, false),
+            (false, false),
+            (false, true),
+            (false, false),
+            (false, true),
+            (true, true),
+        ];
+        assert_eq!(feed(&mut decoder, &bouncy_turn), vec![1]);
+    }
+
+    #[test]
+    fn illegal_transitions_are_rejected() {
+        let mut decoder = QuadratureDecoder::new(CountsPerDetent::One);
+        // 11 -> 00 skips a state in both directions.
+        let recorded = [(false, false), (false, true), (true, true), (false, false)];
+        assert_eq!(feed(&mut decoder, &recorded), vec![1, 1]);
+        assert_eq!(decoder.illegal_transitions(), 2);
+    }
+
+    const CURVE: AccelerationCurve = AccelerationCurve {
+        slow: Duration::from_millis(150),
+        fast: Duration::from_millis(20),
+        min_step: 1,
+        max_step: 101,
+    };
+
+    #[test]
+    fn curve_interpolates() {
+        assert_eq!(CURVE.step_for(Duration::from_millis(1000)), 1);
+        assert_eq!(CURVE.step_for(Duration::from_millis(150)), 1);
+        assert_eq!(CURVE.step_for(Duration::from_millis(85)), 51);
+        assert_eq!(CURVE.step_for(Duration::from_millis(20)), 101);
+        assert_eq!(CURVE.step_for(Duration::ZERO), 101);
+
+        let backwards = AccelerationCurve {
+            min_step: 10,
+            max_step: 5,
+            ..CURVE
+        };
+        for &millis in [0, 20, 85, 150, 1000].iter() {
+            assert_eq!(backwards.step_for(Duration::from_millis(millis)), 10);
+        }
+    }
+
+    #[test]
+    fn slow_turns_are_fine_and_fast_spins_are_coarse() {
+        let mut accelerator = Accelerator::new(CURVE);
+        let steps: Vec<_> = (0..5)
+            .map(|i| accelerator.step(1, Instant::from_millis(i * 500)))
+            .collect();
+        assert_eq!(steps, vec![1; 5]);
+
+        let steps: Vec<_> = (0..20)
+            .map(|i| accelerator.step(1, Instant::from_millis(3000 + i * 10)))
+            .collect();
+        assert!(steps.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(steps.last(), Some(&101));
+        assert_eq!(accelerator.velocity(Instant::from_millis(3190)), 100);
+        assert_eq!(accelerator.velocity(Instant::from_millis(4000)), 0);
+    }
+
+    #[test]
+    fn reversing_starts_slow() {
+        let mut accelerator = Accelerator::new(CURVE);
+        for i in 0..20 {
+            accelerator.step(1, Instant::from_millis(i * 10));
+        }
+        assert_eq!(accelerator.step(-1, Instant::from_millis(200)), -1);
+    }
+
+    #[test]
+    fn huge_steps_keep_their_sign() {
+        let mut accelerator = Accelerator::new(AccelerationCurve {
+            min_step: 40_000,
+            max_step: u16::MAX,
+            ..CURVE
+        });
+        assert_eq!(accelerator.step(1, Instant::from_millis(0)), i16::MAX);
+        assert_eq!(accelerator.step(-1, Instant::from_millis(1)), -i16::MAX);
+    }
+}
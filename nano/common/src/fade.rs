@@ -0,0 +1,203 @@
+//! Ramping channels from one level to another over time.
+//!
+//! A [`Fade`] moves every channel from where it is to a new target over the same
+//! time, along an [`Easing`] curve. It is advanced in whole steps of time, which on
+//! the chip come from a timer interrupt: [`FadeDriver`] is ticked from one and writes
+//! each step to the outputs, so fades stay smooth whatever the main loop is doing.
+//!
+//! Working out where a fade is going and how fast is done once, when it starts.
+//! Each step after that is an addition and a few multiplications, with no
+//! divisions, so it's cheap enough to run with interrupts off.
+
+#[cfg(target_arch = "avr")]
+mod driver;
+
+#[cfg(target_arch = "avr")]
+pub use driver::FadeDriver;
+
+use crate::time::Duration;
+
+/// How progress through a fade maps to progress between the levels.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Easing {
+    /// Constant speed.
+    Linear,
+    /// Starts slow and speeds up.
+    In,
+    /// Starts fast and slows down.
+    Out,
+    /// Slow at both ends.
+    InOut,
+}
+
+const ONE: u32 = u16::MAX as u32;
+
+impl Easing {
+    /// Eased progress for `progress`, both out of `u16::MAX`.
+    pub fn apply(self, progress: u16) -> u16 {
+        // `p * (p + 1) >> 16` squares out of `u16::MAX` as closely as dividing by it
+        // would, and keeps both ends exact
+        let p = progress as u32;
+        let q = ONE - p;
+        let eased = match self {
+            Easing::Linear => p,
+            Easing::In => (p * (p + 1)) >> 16,
+            Easing::Out => ONE - ((q * (q + 1)) >> 16),
+            Easing::InOut if p < ONE / 2 => (p * (p + 1)) >> 15,
+            Easing::InOut => ONE - ((q * (q + 1)) >> 15),
+        };
+        eased as u16
+    }
+}
+
+/// A fade of `N` channels.
+#[derive(Clone, Copy, Debug)]
+pub struct Fade<const N: usize> {
+    from: [u16; N],
+    to: [u16; N],
+    levels: [u16; N],
+    easing: Easing,
+    /// In milliseconds.
+    duration: u32,
+    elapsed: u32,
+    /// Progress out of `u16::MAX`, in 16.16 fixed point, and how much each
+    /// millisecond adds to it.
+    progress: u32,
+    rate: u32,
+}
+
+impl<const N: usize> Fade<N> {
+    /// Every channel at 0.
+    pub const fn new() -> Fade<N> {
+        Fade {
+            from: [0; N],
+            to: [0; N],
+            levels: [0; N],
+            easing: Easing::Linear,
+            duration: 0,
+            elapsed: 0,
+            progress: 0,
+            rate: 0,
+        }
+    }
+
+    /// Start fading from the current levels to `to`. A `duration` of zero jumps
+    /// straight there.
+    pub fn start(&mut self, to: [u16; N], duration: Duration, easing: Easing) {
+        self.from = self.levels;
+        self.to = to;
+        self.easing = easing;
+        self.duration = duration.as_millis();
+        self.elapsed = 0;
+        self.progress = 0;
+        self.rate = (ONE << 16) / self.duration.max(1);
+        if self.duration == 0 {
+            self.levels = to;
+        }
+    }
+
+    pub fn levels(&self) -> [u16; N] {
+        self.levels
+    }
+
+    /// Levels the fade is heading for.
+    pub fn target(&self) -> [u16; N] {
+        self.to
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.levels == self.to
+    }
+
+    /// Move on by `time`. Returns whether any level changed.
+    pub fn advance(&mut self, time: Duration) -> bool {
+        if self.is_done() {
+            return false;
+        }
+        let time = time.as_millis().min(self.duration - self.elapsed);
+        self.elapsed += time;
+        self.progress = self.progress.saturating_add(self.rate.saturating_mul(time));
+        let eased = self.easing.apply((self.progress >> 16) as u16) as u32;
+        let last = self.levels;
+        for ((level, &from), &to) in self.levels.iter_mut().zip(&self.from).zip(&self.to) {
+            *level = if to >= from {
+                from + (((to - from) as u32 * eased + 0x8000) >> 16) as u16
+            } else {
+                from - (((from - to) as u32 * eased + 0x8000) >> 16) as u16
+            };
+        }
+        if self.elapsed == self.duration {
+            self.levels = self.to;
+        }
+        self.levels != last
+    }
+}
+
+impl<const N: usize> Default for Fade<N> {
+    fn default() -> Fade<N> {
+        Fade::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EASINGS: [Easing; 4] = [Easing::Linear, Easing::In, Easing::Out, Easing::InOut];
+
+    #[test]
+    fn easings_run_from_start_to_end() {
+        for &easing in EASINGS.iter() {
+            assert_eq!(easing.apply(0), 0);
+            assert_eq!(easing.apply(u16::MAX), u16::MAX);
+            let steps: Vec<u16> = (0..=u16::MAX)
+                .step_by(64)
+                .map(|p| easing.apply(p))
+                .collect();
+            assert!(steps.windows(2).all(|pair| pair[0] <= pair[1]));
+        }
+        assert!(Easing::In.apply(16384) < 16384);
+        assert!(Easing::Out.apply(16384) > 16384);
+        assert!(Easing::InOut.apply(16384) < 16384);
+        assert!(Easing::InOut.apply(49152) > 49152);
+    }
+
+    #[test]
+    fn fades_both_ways_at_once() {
+        let mut fade = Fade::new();
+        fade.start([1000, 0], Duration::ZERO, Easing::Linear);
+        assert_eq!(fade.levels(), [1000, 0]);
+
+        fade.start([0, 1000], Duration::from_millis(100), Easing::Linear);
+        assert!(fade.advance(Duration::from_millis(25)));
+        assert_eq!(fade.levels(), [750, 250]);
+        fade.advance(Duration::from_millis(25));
+        assert_eq!(fade.levels(), [500, 500]);
+        fade.advance(Duration::from_millis(100));
+        assert_eq!(fade.levels(), [0, 1000]);
+        assert!(fade.is_done());
+        assert!(!fade.advance(Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn new_target_starts_from_where_the_fade_got_to() {
+        let mut fade = Fade::new();
+        fade.start([1000], Duration::from_millis(10), Easing::In);
+        for _ in 0..5 {
+            fade.advance(Duration::from_millis(1));
+        }
+        assert_eq!(fade.levels(), [250]);
+        fade.start([0], Duration::from_millis(10), Easing::Linear);
+        fade.advance(Duration::from_millis(5));
+        assert_eq!(fade.levels(), [125]);
+    }
+
+    #[test]
+    fn long_fades_keep_moving() {
+        let mut fade = Fade::new();
+        fade.start([u16::MAX], Duration::from_secs(600), Easing::Linear);
+        assert!(fade.advance(Duration::from_secs(1)));
+        assert!(fade.advance(Duration::from_secs(1)));
+        assert_eq!(fade.levels(), [218]);
+    }
+}
@@ -0,0 +1,127 @@
+//! Fades stepped by a timer interrupt.
+
+use super::{Easing, Fade};
+use crate::light::Outputs;
+use crate::time::Duration;
+use avr_device::interrupt::{self, Mutex};
+use core::cell::RefCell;
+
+struct State<O, const N: usize> {
+    fade: Fade<N>,
+    outputs: Option<O>,
+    duration: Duration,
+    easing: Easing,
+    /// Ticks a second, and each tick's worth of time as whole milliseconds and
+    /// thousandths of a tick over.
+    tick_rate: u32,
+    tick_millis: u32,
+    tick_remainder: u32,
+    /// Thousandths of a tick's worth of time not yet used.
+    owed: u32,
+}
+
+/// A fade shared with the timer interrupt that steps it, and the outputs it drives.
+/// Meant to live in a `static`.
+///
+/// Any interrupt that fires at a steady rate can tick it, such as a
+/// [`TickTimer`](crate::pwm::TickTimer)'s or the overflow of a timer already running
+/// PWM, alongside whatever else that interrupt does. The outputs are written from
+/// the interrupt, so they have to be handles that don't borrow anything, like a
+/// [`CompareWriter`](crate::pwm::CompareWriter).
+pub struct FadeDriver<O, const N: usize> {
+    state: Mutex<RefCell<State<O, N>>>,
+}
+
+impl<O: Outputs<N>, const N: usize> FadeDriver<O, N> {
+    /// [`FadeDriver::fade`] takes `duration` along `easing`.
+    pub const fn new(duration: Duration, easing: Easing) -> FadeDriver<O, N> {
+        FadeDriver {
+            state: Mutex::new(RefCell::new(State {
+                fade: Fade::new(),
+                outputs: None,
+                duration,
+                easing,
+                tick_rate: 0,
+                tick_millis: 0,
+                tick_remainder: 0,
+                owed: 0,
+            })),
+        }
+    }
+
+    /// Hand over `outputs`, all set to 0, to be faded by [`FadeDriver::tick`] being
+    /// called `tick_rate` times a second. The fade moves on a millisecond at a time,
+    /// so anything from 1kHz up is smooth.
+    pub fn start(&self, mut outputs: O, tick_rate: u32) {
+        outputs.set_levels(&[0; N]);
+        interrupt::free(|cs| {
+            let state = &mut *self.state.borrow(cs).borrow_mut();
+            state.outputs = Some(outputs);
+            state.tick_rate = tick_rate;
+            state.tick_millis = 1_000 / tick_rate.max(1);
+            state.tick_remainder = 1_000 % tick_rate.max(1);
+            state.owed = 0;
+        });
+    }
+
+    /// Count a tick, moving the fade on whenever another millisecond has passed.
+    /// Call from the interrupt the driver was started with the rate of.
+    ///
+    /// Between milliseconds this is an addition and a comparison. On one it's also a
+    /// step of the [`Fade`] and a write to the outputs.
+    pub fn tick(&self) {
+        interrupt::free(|cs| {
+            let state = &mut *self.state.borrow(cs).borrow_mut();
+            if state.tick_rate == 0 {
+                return;
+            }
+            let mut millis = state.tick_millis;
+            state.owed += state.tick_remainder;
+            if state.owed >= state.tick_rate {
+                state.owed -= state.tick_rate;
+                millis += 1;
+            }
+            if millis > 0 && state.fade.advance(Duration::from_millis(millis)) {
+                if let Some(outputs) = &mut state.outputs {
+                    outputs.set_levels(&state.fade.levels());
+                }
+            }
+        });
+    }
+
+    /// Fade from wherever the outputs are to `to`, with the driver's own duration and
+    /// easing.
+    pub fn fade(&self, to: [u16; N]) {
+        let (duration, easing) = interrupt::free(|cs| {
+            let state = self.state.borrow(cs).borrow();
+            (state.duration, state.easing)
+        });
+        self.fade_with(to, duration, easing);
+    }
+
+    /// Fade from wherever the outputs are to `to`, taking `duration` along `easing`.
+    pub fn fade_with(&self, to: [u16; N], duration: Duration, easing: Easing) {
+        interrupt::free(|cs| {
+            let state = &mut *self.state.borrow(cs).borrow_mut();
+            state.fade.start(to, duration, easing);
+            if let Some(outputs) = &mut state.outputs {
+                outputs.set_levels(&state.fade.levels());
+            }
+        });
+    }
+
+    pub fn levels(&self) -> [u16; N] {
+        interrupt::free(|cs| self.state.borrow(cs).borrow().fade.levels())
+    }
+
+    pub fn is_fading(&self) -> bool {
+        interrupt::free(|cs| !self.state.borrow(cs).borrow().fade.is_done())
+    }
+}
+
+/// Fades to the levels with the driver's own duration and easing.
+impl<O: Outputs<N>, const N: usize> Outputs<N> for &FadeDriver<O, N> {
+    fn set_levels(&mut self, levels: &[u16; N]) {
+        self.fade(*levels);
+    }
+}
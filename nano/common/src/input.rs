@@ -0,0 +1,292 @@
+//! One event type for every kind of user input.
+//!
+//! Each peripheral is wrapped in an [`InputSource`] that turns whatever it produces
+//! into [`InputEvent`]s. The [`Dispatcher`] collects them from all sources in the
+//! order they are polled and hands them to the application one at a time, so the
+//! application doesn't need to know which peripheral an event came from.
+
+use crate::button::{Button, ButtonEvent, GestureDetector, GestureTimings};
+use crate::queue::Queue;
+use crate::time::Clock;
+use embedded_hal::digital::v2::InputPin;
+use embedded_hal::serial::Read;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InputEvent {
+    Button {
+        id: u8,
+        event: ButtonEvent,
+    },
+    /// An encoder was turned by `delta` steps.
+    Encoder {
+        id: u8,
+        delta: i16,
+    },
+    /// An analog input settled on a new value.
+    Analog {
+        id: u8,
+        value: u16,
+    },
+    Serial(Command),
+}
+
+/// A line typed on the serial port.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Command {
+    On,
+    Off,
+    Toggle,
+    /// `b <n>`
+    Brightness(u16),
+    /// `t <n>`
+    Temperature(u16),
+}
+
+pub trait InputSource {
+    /// Next event this source has ready, if any.
+    fn poll(&mut self) -> Option<InputEvent>;
+}
+
+impl<S: InputSource> InputSource for &mut S {
+    fn poll(&mut self) -> Option<InputEvent> {
+        (**self).poll()
+    }
+}
+
+/// Events pushed by interrupt handlers.
+impl<const N: usize> InputSource for &Queue<InputEvent, N> {
+    fn poll(&mut self) -> Option<InputEvent> {
+        self.pop()
+    }
+}
+
+/// A button with gesture detection.
+pub struct ButtonInput<P, C> {
+    id: u8,
+    button: Button<P, C>,
+    gestures: GestureDetector,
+}
+
+impl<P: InputPin, C: Clock> ButtonInput<P, C> {
+    pub fn new(id: u8, button: Button<P, C>, timings: GestureTimings) -> ButtonInput<P, C> {
+        ButtonInput {
+            id,
+            button,
+            gestures: GestureDetector::new(timings),
+        }
+    }
+
+    pub fn button(&self) -> &Button<P, C> {
+        &self.button
+    }
+}
+
+impl<P: InputPin, C: Clock> InputSource for ButtonInput<P, C> {
+    fn poll(&mut self) -> Option<InputEvent> {
+        self.button.update().ok()?;
+        let event = self
+            .gestures
+            .update(self.button.is_pressed(), self.button.now())?;
+        Some(InputEvent::Button { id: self.id, event })
+    }
+}
+
+const LINE_LENGTH: usize = 16;
+
+/// Assembles serial bytes into [`Command`]s, one per line. Lines that don't parse are
+/// dropped.
+pub struct CommandParser {
+    line: [u8; LINE_LENGTH],
+    len: usize,
+}
+
+impl CommandParser {
+    pub const fn new() -> CommandParser {
+        CommandParser {
+            line: [0; LINE_LENGTH],
+            len: 0,
+        }
+    }
+
+    /// Feed in one byte, returning a command once a line is complete.
+    pub fn feed(&mut self, byte: u8) -> Option<Command> {
+        if byte != b'\n' && byte != b'\r' {
+            if self.len < LINE_LENGTH {
+                self.line[self.len] = byte;
+            }
+            // Only needs to remember that the line is too long, not by how much
+            self.len = (self.len + 1).min(LINE_LENGTH + 1);
+            return None;
+        }
+        let len = self.len;
+        self.len = 0;
+        if len > LINE_LENGTH {
+            return None;
+        }
+        parse(core::str::from_utf8(&self.line[..len]).ok()?.trim())
+    }
+
+    /// Source reading commands from `reader` for as long as it has bytes waiting.
+    pub fn reading<'a, R: Read<u8>>(&'a mut self, reader: &'a mut R) -> SerialInput<'a, R> {
+        SerialInput {
+            parser: self,
+            reader,
+        }
+    }
+}
+
+impl Default for CommandParser {
+    fn default() -> CommandParser {
+        CommandParser::new()
+    }
+}
+
+fn parse(line: &str) -> Option<Command> {
+    match line {
+        "on" => return Some(Command::On),
+        "off" => return Some(Command::Off),
+        "toggle" => return Some(Command::Toggle),
+        _ => {}
+    }
+    let mut words = line.split_whitespace();
+    let command = words.next()?;
+    let value = words.next()?.parse().ok()?;
+    if words.next().is_some() {
+        return None;
+    }
+    match command {
+        "b" => Some(Command::Brightness(value)),
+        "t" => Some(Command::Temperature(value)),
+        _ => None,
+    }
+}
+
+pub struct SerialInput<'a, R> {
+    parser: &'a mut CommandParser,
+    reader: &'a mut R,
+}
+
+impl<R: Read<u8>> InputSource for SerialInput<'_, R> {
+    fn poll(&mut self) -> Option<InputEvent> {
+        while let Ok(byte) = self.reader.read() {
+            if let Some(command) = self.parser.feed(byte) {
+                return Some(InputEvent::Serial(command));
+            }
+        }
+        None
+    }
+}
+
+/// Collects events from any number of sources into a single stream.
+///
+/// Only meant to be used from the main loop. Interrupt handlers should push into a
+/// [`Queue`] of their own, which is then taken from like any other source.
+pub struct Dispatcher<const N: usize> {
+    pending: Queue<InputEvent, N>,
+}
+
+impl<const N: usize> Dispatcher<N> {
+    pub const fn new() -> Dispatcher<N> {
+        Dispatcher {
+            pending: Queue::new(),
+        }
+    }
+
+    /// Take every event `source` has ready, or as many as there is room for.
+    pub fn take_from<S: InputSource>(&mut self, mut source: S) {
+        while self.pending.len() < self.pending.capacity() {
+            match source.poll() {
+                Some(event) => {
+                    self.pending.push(event).ok();
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// Add an event that didn't come from an [`InputSource`].
+    pub fn post(&mut self, event: InputEvent) -> Result<(), InputEvent> {
+        self.pending.push(event)
+    }
+}
+
+impl<const N: usize> Default for Dispatcher<N> {
+    fn default() -> Dispatcher<N> {
+        Dispatcher::new()
+    }
+}
+
+impl<const N: usize> Iterator for Dispatcher<N> {
+    type Item = InputEvent;
+
+    fn next(&mut self) -> Option<InputEvent> {
+        self.pending.pop()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed_line(parser: &mut CommandParser, line: &str) -> Option<Command> {
+        line.bytes().filter_map(|byte| parser.feed(byte)).last()
+    }
+
+    #[test]
+    fn parses_commands() {
+        let mut parser = CommandParser::new();
+        assert_eq!(feed_line(&mut parser, "on\n"), Some(Command::On));
+        assert_eq!(feed_line(&mut parser, "off\r\n"), Some(Command::Off));
+        assert_eq!(
+            feed_line(&mut parser, "b 512\n"),
+            Some(Command::Brightness(512))
+        );
+        assert_eq!(
+            feed_line(&mut parser, " t  40 \n"),
+            Some(Command::Temperature(40))
+        );
+        assert_eq!(feed_line(&mut parser, "b\n"), None);
+        assert_eq!(feed_line(&mut parser, "b 1 2\n"), None);
+        assert_eq!(
+            feed_line(&mut parser, "much too long to be a command\n"),
+            None
+        );
+        assert_eq!(feed_line(&mut parser, "toggle\n"), Some(Command::Toggle));
+        for _ in 0..70_000 {
+            parser.feed(b'x');
+        }
+        assert_eq!(feed_line(&mut parser, "\non\n"), Some(Command::On));
+    }
+
+    #[test]
+    fn dispatcher_keeps_source_order() {
+        let isr: Queue<InputEvent, 4> = Queue::new();
+        isr.push(InputEvent::Encoder { id: 0, delta: 1 }).unwrap();
+        isr.push(InputEvent::Encoder { id: 0, delta: -1 }).unwrap();
+
+        let mut dispatcher: Dispatcher<8> = Dispatcher::new();
+        dispatcher.post(InputEvent::Serial(Command::On)).unwrap();
+        dispatcher.take_from(&isr);
+        assert!(isr.is_empty());
+        assert_eq!(
+            dispatcher.collect::<Vec<_>>(),
+            vec![
+                InputEvent::Serial(Command::On),
+                InputEvent::Encoder { id: 0, delta: 1 },
+                InputEvent::Encoder { id: 0, delta: -1 },
+            ]
+        );
+    }
+
+    #[test]
+    fn full_dispatcher_leaves_events_in_source() {
+        let isr: Queue<InputEvent, 8> = Queue::new();
+        for value in 0..5 {
+            isr.push(InputEvent::Analog { id: 0, value }).unwrap();
+        }
+        let mut dispatcher: Dispatcher<4> = Dispatcher::new();
+        dispatcher.take_from(&isr);
+        assert_eq!(isr.len(), 2);
+        assert_eq!(dispatcher.count(), 3);
+    }
+}
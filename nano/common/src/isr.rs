@@ -0,0 +1,52 @@
+//! State shared between interrupt handlers and the main program.
+
+use avr_device::interrupt::{self, CriticalSection, Mutex};
+use core::cell::RefCell;
+
+/// A value that is moved in once during setup and then borrowed inside critical
+/// sections.
+///
+/// Interrupts can fire before setup has got round to filling it in, so every access
+/// hands back an `Option` and does nothing until [`IsrShared::init`] has been called.
+pub struct IsrShared<T> {
+    inner: Mutex<RefCell<Option<T>>>,
+}
+
+impl<T> IsrShared<T> {
+    pub const fn new() -> IsrShared<T> {
+        IsrShared {
+            inner: Mutex::new(RefCell::new(None)),
+        }
+    }
+
+    /// Move the value in.
+    ///
+    /// # Panics
+    ///
+    /// If the value has already been set.
+    pub fn init(&self, value: T) {
+        interrupt::free(|cs| {
+            let mut inner = self.inner.borrow(cs).borrow_mut();
+            assert!(inner.is_none());
+            *inner = Some(value);
+        })
+    }
+
+    /// Run `f` on the value if it has been set and isn't already borrowed further up
+    /// the stack.
+    pub fn with<R>(&self, cs: &CriticalSection, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        let mut inner = self.inner.borrow(cs).try_borrow_mut().ok()?;
+        inner.as_mut().map(f)
+    }
+
+    /// [`IsrShared::with`] in a critical section of its own.
+    pub fn lock<R>(&self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        interrupt::free(|cs| self.with(cs, f))
+    }
+}
+
+impl<T> Default for IsrShared<T> {
+    fn default() -> IsrShared<T> {
+        IsrShared::new()
+    }
+}
@@ -0,0 +1,27 @@
+//! Code shared between the nano examples.
+//!
+//! Anything that touches the chip directly is only built for AVR. The rest is plain
+//! `no_std` code that also builds for the host, which is where its tests run.
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(target_arch = "avr", feature(abi_avr_interrupt))]
+
+pub mod analog;
+pub mod button;
+pub mod curve;
+pub mod dither;
+pub mod effect;
+pub mod encoder;
+pub mod fade;
+pub mod input;
+#[cfg(target_arch = "avr")]
+pub mod isr;
+pub mod light;
+#[cfg(target_arch = "avr")]
+pub mod millis;
+pub mod mix;
+pub mod pwm;
+pub mod queue;
+pub mod softpwm;
+pub mod status;
+pub mod time;
+pub mod white;
@@ -0,0 +1,320 @@
+//! A lamp made of any number of output channels.
+//!
+//! The [`LightEngine`] holds the lamp's settings (power, brightness and colour) and
+//! turns them into a level for each channel through a [`ColorModel`]. Every change
+//! fades across all the channels together over the engine's transition time.
+//!
+//! Channels are anything that takes a 16-bit level: hardware PWM, dithered and
+//! software PWM outputs all do. So does a [`FadeDriver`](crate::fade::FadeDriver),
+//! which fades them from a timer interrupt instead, for an engine with no transition
+//! of its own.
+
+use crate::curve::Curve;
+use crate::dither::MAX_LEVEL;
+use crate::fade::{Easing, Fade};
+use crate::time::{Duration, Instant};
+use crate::white::TunableWhite;
+
+/// A single channel of light.
+pub trait LightOutput {
+    /// Set the light output, out of [`MAX_LEVEL`].
+    fn set_level(&mut self, level: u16);
+}
+
+/// All of a lamp's channels.
+pub trait Outputs<const N: usize> {
+    fn set_levels(&mut self, levels: &[u16; N]);
+}
+
+impl<O: LightOutput, const N: usize> Outputs<N> for [O; N] {
+    fn set_levels(&mut self, levels: &[u16; N]) {
+        for (output, &level) in self.iter_mut().zip(levels.iter()) {
+            output.set_level(level);
+        }
+    }
+}
+
+/// What the channels are, and so how a colour is made from them.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ColorModel {
+    /// Every channel is the same colour and at the same level.
+    Single,
+    /// A warm and a cool white channel, in that order.
+    TunableWhite(TunableWhite),
+    /// Red, green and blue channels.
+    Rgb,
+    /// Red, green, blue and white channels. The white channel takes over the part of
+    /// the colour all three have in common.
+    Rgbw,
+}
+
+impl ColorModel {
+    fn fits(self, channels: usize) -> bool {
+        match self {
+            ColorModel::Single => channels > 0,
+            ColorModel::TunableWhite(_) => channels == 2,
+            ColorModel::Rgb => channels == 3,
+            ColorModel::Rgbw => channels == 4,
+        }
+    }
+}
+
+pub struct LightEngine<O, const N: usize> {
+    outputs: O,
+    model: ColorModel,
+    curve: Curve,
+    transition: Duration,
+    on: bool,
+    brightness: u16,
+    kelvin: u16,
+    rgb: [u8; 3],
+    fade: Fade<N>,
+    /// Levels last written to the outputs.
+    levels: [u16; N],
+    updated: Instant,
+}
+
+impl<O: Outputs<N>, const N: usize> LightEngine<O, N> {
+    /// Starts off, at full brightness, with every channel's level at 0. The colour
+    /// starts as white for the RGB models and at the warm end for tunable white.
+    ///
+    /// # Panics
+    ///
+    /// If `model` needs a different number of channels.
+    pub fn new(mut outputs: O, model: ColorModel) -> LightEngine<O, N> {
+        assert!(model.fits(N));
+        let levels = [0; N];
+        outputs.set_levels(&levels);
+        let kelvin = match model {
+            ColorModel::TunableWhite(lamp) => lamp.warm_kelvin,
+            _ => 0,
+        };
+        LightEngine {
+            outputs,
+            model,
+            curve: Curve::Linear,
+            transition: Duration::ZERO,
+            on: false,
+            brightness: MAX_LEVEL,
+            kelvin,
+            rgb: [u8::MAX; 3],
+            fade: Fade::new(),
+            levels,
+            updated: Instant::from_millis(0),
+        }
+    }
+
+    /// Curve brightness is mapped through. Defaults to [`Curve::Linear`].
+    pub fn with_curve(mut self, curve: Curve) -> Self {
+        self.curve = curve;
+        self
+    }
+
+    /// Time each change takes to fade in. Defaults to none.
+    pub fn with_transition(mut self, transition: Duration) -> Self {
+        self.transition = transition;
+        self
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.on
+    }
+
+    pub fn set_on(&mut self, on: bool, now: Instant) {
+        self.on = on;
+        self.retarget(now);
+    }
+
+    pub fn toggle(&mut self, now: Instant) {
+        self.set_on(!self.on, now);
+    }
+
+    /// Perceived brightness, out of [`MAX_LEVEL`].
+    pub fn brightness(&self) -> u16 {
+        self.brightness
+    }
+
+    pub fn set_brightness(&mut self, brightness: u16, now: Instant) {
+        self.brightness = brightness;
+        self.retarget(now);
+    }
+
+    /// Colour temperature for [`ColorModel::TunableWhite`].
+    pub fn kelvin(&self) -> u16 {
+        self.kelvin
+    }
+
+    pub fn set_kelvin(&mut self, kelvin: u16, now: Instant) {
+        self.kelvin = match self.model {
+            ColorModel::TunableWhite(lamp) => lamp.clamp(kelvin),
+            _ => kelvin,
+        };
+        self.retarget(now);
+    }
+
+    /// Colour for [`ColorModel::Rgb`] and [`ColorModel::Rgbw`].
+    pub fn rgb(&self) -> [u8; 3] {
+        self.rgb
+    }
+
+    pub fn set_rgb(&mut self, rgb: [u8; 3], now: Instant) {
+        self.rgb = rgb;
+        self.retarget(now);
+    }
+
+    /// Levels the channels are at.
+    pub fn levels(&self) -> [u16; N] {
+        self.levels
+    }
+
+    /// Levels the channels are fading towards.
+    pub fn targets(&self) -> [u16; N] {
+        self.fade.target()
+    }
+
+    pub fn outputs(&mut self) -> &mut O {
+        &mut self.outputs
+    }
+
+    /// Move any fade on to `now`. Returns whether it has further to go.
+    pub fn update(&mut self, now: Instant) -> bool {
+        self.fade.advance(now.since(self.updated));
+        self.updated = now;
+        self.write_levels();
+        !self.fade.is_done()
+    }
+
+    fn retarget(&mut self, now: Instant) {
+        self.update(now);
+        self.fade
+            .start(self.target(), self.transition, Easing::Linear);
+        self.write_levels();
+    }
+
+    fn write_levels(&mut self) {
+        let levels = self.fade.levels();
+        if levels != self.levels {
+            self.levels = levels;
+            self.outputs.set_levels(&levels);
+        }
+    }
+
+    fn target(&self) -> [u16; N] {
+        let mut levels = [0; N];
+        if !self.on {
+            return levels;
+        }
+        let level = self.curve.level(self.brightness as u32, MAX_LEVEL as u32);
+        match self.model {
+            ColorModel::Single => levels = [level; N],
+            ColorModel::TunableWhite(lamp) => {
+                let (warm, cool) = lamp.mix(self.kelvin, level);
+                levels[0] = warm;
+                levels[1] = cool;
+            }
+            ColorModel::Rgb => {
+                for (channel, &value) in levels.iter_mut().zip(self.rgb.iter()) {
+                    *channel = scale(value, level);
+                }
+            }
+            ColorModel::Rgbw => {
+                let white = self.rgb.iter().copied().min().unwrap_or(0);
+                for (channel, &value) in levels.iter_mut().zip(self.rgb.iter()) {
+                    *channel = scale(value - white, level);
+                }
+                levels[3] = scale(white, level);
+            }
+        }
+        levels
+    }
+}
+
+/// `value` out of 255 of `level`.
+fn scale(value: u8, level: u16) -> u16 {
+    (value as u32 * level as u32 / u8::MAX as u32) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mix::MixMode;
+
+    #[derive(Default)]
+    struct Recorded(u16);
+
+    impl LightOutput for Recorded {
+        fn set_level(&mut self, level: u16) {
+            self.0 = level;
+        }
+    }
+
+    fn at(millis: u32) -> Instant {
+        Instant::from_millis(millis)
+    }
+
+    fn recorded<const N: usize>(engine: &mut LightEngine<[Recorded; N], N>) -> Vec<u16> {
+        engine.outputs().iter().map(|output| output.0).collect()
+    }
+
+    #[test]
+    fn power_and_brightness_apply_to_every_channel() {
+        let outputs: [Recorded; 3] = Default::default();
+        let mut engine = LightEngine::new(outputs, ColorModel::Single);
+        engine.set_brightness(MAX_LEVEL / 2, at(0));
+        assert_eq!(recorded(&mut engine), [0, 0, 0]);
+        engine.set_on(true, at(0));
+        assert_eq!(recorded(&mut engine), [32767, 32767, 32767]);
+        engine.toggle(at(0));
+        assert_eq!(recorded(&mut engine), [0, 0, 0]);
+    }
+
+    #[test]
+    fn color_models_split_the_light() {
+        let lamp = TunableWhite::new(2700, 6500, MixMode::ConstantTotal);
+        let outputs: [Recorded; 2] = Default::default();
+        let mut white = LightEngine::new(outputs, ColorModel::TunableWhite(lamp));
+        white.set_on(true, at(0));
+        assert_eq!(recorded(&mut white), [MAX_LEVEL, 0]);
+        white.set_kelvin(10_000, at(0));
+        assert_eq!(white.kelvin(), 6500);
+        assert_eq!(recorded(&mut white), [0, MAX_LEVEL]);
+
+        let outputs: [Recorded; 3] = Default::default();
+        let mut rgb = LightEngine::new(outputs, ColorModel::Rgb);
+        rgb.set_on(true, at(0));
+        rgb.set_rgb([255, 0, 51], at(0));
+        assert_eq!(recorded(&mut rgb), [MAX_LEVEL, 0, 13107]);
+
+        let outputs: [Recorded; 4] = Default::default();
+        let mut rgbw = LightEngine::new(outputs, ColorModel::Rgbw);
+        rgbw.set_on(true, at(0));
+        assert_eq!(recorded(&mut rgbw), [0, 0, 0, MAX_LEVEL]);
+        rgbw.set_rgb([255, 102, 51], at(0));
+        assert_eq!(recorded(&mut rgbw), [52428, 13107, 0, 13107]);
+    }
+
+    #[test]
+    fn changes_fade_in() {
+        let outputs: [Recorded; 2] = Default::default();
+        let mut engine = LightEngine::new(outputs, ColorModel::Single)
+            .with_transition(Duration::from_millis(100));
+        engine.set_on(true, at(1000));
+        assert_eq!(recorded(&mut engine), [0, 0]);
+        assert!(engine.update(at(1050)));
+        assert_eq!(recorded(&mut engine), [32767, 32767]);
+
+        // Turning off part way through fades down from where it got to
+        engine.set_on(false, at(1050));
+        assert!(engine.update(at(1100)));
+        assert_eq!(recorded(&mut engine), [16384, 16384]);
+        assert!(!engine.update(at(1150)));
+        assert_eq!(engine.levels(), [0, 0]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn model_needs_its_channels() {
+        let outputs: [Recorded; 3] = Default::default();
+        LightEngine::new(outputs, ColorModel::Rgbw);
+    }
+}
@@ -0,0 +1,70 @@
+//! Millisecond clock running off TC0.
+//!
+//! TC0 is put in CTC mode and fires `TIMER0_COMPA` every [`MILLIS_INCREMENT`] ms,
+//! which bumps a global counter. Call [`Millis::init`] once at startup and then read
+//! the counter through the [`Clock`] it hands back, which is the only way to, so
+//! nothing can read a clock that was never started.
+
+use crate::time::{Clock, Instant};
+use arduino_hal::pac::TC0;
+use avr_device::interrupt::Mutex;
+use core::cell::Cell;
+
+const PRESCALER: u32 = 1024;
+const TIMER_COUNTS: u32 = 125;
+
+/// Number of milliseconds between two ticks of the counter.
+pub const MILLIS_INCREMENT: u32 = PRESCALER * TIMER_COUNTS / 16000;
+
+static MILLIS_COUNTER: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+
+/// Handle to the running millisecond clock.
+///
+/// The only way to get one is to hand over `TC0`, so the timer can't be configured
+/// twice and nothing else can reuse it behind the clock's back.
+#[derive(Clone, Copy)]
+pub struct Millis {
+    _private: (),
+}
+
+impl Millis {
+    /// Start the clock. Interrupts still need to be enabled for it to tick.
+    pub fn init(tc0: TC0) -> Millis {
+        // Configure the timer for the above interval (in CTC mode)
+        // and enable its interrupt.
+        tc0.tccr0a.write(|w| w.wgm0().ctc());
+        tc0.ocr0a.write(|w| unsafe { w.bits(TIMER_COUNTS as u8) });
+        tc0.tccr0b.write(|w| match PRESCALER {
+            8 => w.cs0().prescale_8(),
+            64 => w.cs0().prescale_64(),
+            256 => w.cs0().prescale_256(),
+            1024 => w.cs0().prescale_1024(),
+            _ => panic!(),
+        });
+        tc0.timsk0.write(|w| w.ocie0a().set_bit());
+
+        // Reset the global millisecond counter
+        avr_device::interrupt::free(|cs| {
+            MILLIS_COUNTER.borrow(cs).set(0);
+        });
+
+        Millis { _private: () }
+    }
+}
+
+impl Clock for Millis {
+    fn now(&self) -> Instant {
+        Instant::from_millis(avr_device::interrupt::free(|cs| {
+            MILLIS_COUNTER.borrow(cs).get()
+        }))
+    }
+}
+
+#[avr_device::interrupt(atmega328p)]
+fn TIMER0_COMPA() {
+    avr_device::interrupt::free(|cs| {
+        let counter_cell = MILLIS_COUNTER.borrow(cs);
+        let counter = counter_cell.get();
+        counter_cell.set(counter.wrapping_add(MILLIS_INCREMENT));
+    })
+}
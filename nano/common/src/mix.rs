@@ -0,0 +1,106 @@
+//! Splitting a lamp's brightness between a warm and a cool channel.
+
+/// How the light is shared out as the temperature moves between the two ends.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MixMode {
+    /// The channels add up to the brightness at every temperature, so only the colour
+    /// changes.
+    ConstantTotal,
+    /// Each channel stays at full brightness until the temperature is past the middle
+    /// and then fades out towards the far end. Gives the most light, but the lamp is
+    /// twice as bright in the middle as at either end.
+    Overlap,
+}
+
+/// Mixes a brightness and a temperature into warm and cool channel brightnesses.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Mixer {
+    pub mode: MixMode,
+    /// Temperature at which the light is all warm. At 0 it is all cool.
+    pub temp_max: u16,
+}
+
+impl Mixer {
+    pub const fn new(mode: MixMode, temp_max: u16) -> Mixer {
+        Mixer { mode, temp_max }
+    }
+
+    /// `(warm, cool)` brightness, in the same units as `brightness` and never more than
+    /// it. `temp` is clamped to [`Mixer::temp_max`].
+    pub fn mix(&self, temp: u16, brightness: u16) -> (u16, u16) {
+        let max = self.temp_max.max(1) as u32;
+        let temp = (temp as u32).min(max);
+        let brightness = brightness as u32;
+        match self.mode {
+            MixMode::ConstantTotal => {
+                let warm = brightness * temp / max;
+                (warm as u16, (brightness - warm) as u16)
+            }
+            MixMode::Overlap => {
+                let warm = brightness * (2 * temp).min(max) / max;
+                let cool = brightness * (2 * (max - temp)).min(max) / max;
+                (warm as u16, cool as u16)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODES: [MixMode; 2] = [MixMode::ConstantTotal, MixMode::Overlap];
+
+    #[test]
+    fn ends_are_all_one_channel() {
+        for &mode in MODES.iter() {
+            let mixer = Mixer::new(mode, 2046);
+            assert_eq!(mixer.mix(0, 1023), (0, 1023));
+            assert_eq!(mixer.mix(2046, 1023), (1023, 0));
+            assert_eq!(mixer.mix(5000, 1023), (1023, 0));
+            assert_eq!(mixer.mix(1023, 0), (0, 0));
+        }
+    }
+
+    #[test]
+    fn channels_move_steadily_and_stay_in_range() {
+        for &mode in MODES.iter() {
+            let mixer = Mixer::new(mode, 255);
+            for brightness in [0, 1, 100, 255, u16::MAX].iter().copied() {
+                let mixes: Vec<_> = (0..=255).map(|temp| mixer.mix(temp, brightness)).collect();
+                assert!(mixes
+                    .iter()
+                    .all(|&(warm, cool)| warm <= brightness && cool <= brightness));
+                assert!(mixes
+                    .windows(2)
+                    .all(|pair| pair[0].0 <= pair[1].0 && pair[0].1 >= pair[1].1));
+            }
+            for temp in 0..=255 {
+                let mixes: Vec<_> = (0..=255)
+                    .map(|brightness| mixer.mix(temp, brightness))
+                    .collect();
+                assert!(mixes
+                    .windows(2)
+                    .all(|pair| pair[0].0 <= pair[1].0 && pair[0].1 <= pair[1].1));
+            }
+        }
+    }
+
+    #[test]
+    fn constant_total_keeps_brightness() {
+        let mixer = Mixer::new(MixMode::ConstantTotal, 2046);
+        for temp in 0..=2046 {
+            let (warm, cool) = mixer.mix(temp, 1000);
+            assert_eq!(warm + cool, 1000);
+        }
+        assert_eq!(mixer.mix(1023, 1000), (500, 500));
+    }
+
+    #[test]
+    fn overlap_is_brightest_in_the_middle() {
+        let mixer = Mixer::new(MixMode::Overlap, 2046);
+        assert_eq!(mixer.mix(1023, 1023), (1023, 1023));
+        assert_eq!(mixer.mix(500, 1023), (500, 1023));
+        assert_eq!(mixer.mix(1546, 1023), (1023, 500));
+    }
+}
@@ -0,0 +1,335 @@
+//! Hardware PWM on the six output compare pins.
+//!
+//! | Pin | Output | Timer |
+//! |-----|--------|-------|
+//! | d6  | OC0A   | TC0   |
+//! | d5  | OC0B   | TC0   |
+//! | d9  | OC1A   | TC1   |
+//! | d10 | OC1B   | TC1   |
+//! | d11 | OC2A   | TC2   |
+//! | d3  | OC2B   | TC2   |
+//!
+//! Each timer is configured once by its driver, which fixes the [`Mode`], frequency
+//! and [`Resolution`] for both of its pins. The pins are then taken as
+//! [`PwmChannel`]s that borrow the driver, so the timer can't be reconfigured
+//! underneath them and each one only ever touches its own compare register and
+//! output bits.
+//!
+//! TC1 or TC2 can instead be given over to a [`TickTimer`], to run drivers that are
+//! ticked from an interrupt.
+//!
+//! TC0 also runs the millis clock, so d5 and d6 can only be used for PWM in programs
+//! that don't need it.
+
+use core::marker::PhantomData;
+
+#[cfg(target_arch = "avr")]
+mod timer;
+
+#[cfg(target_arch = "avr")]
+pub use timer::{
+    CompareWriter, ManagedChannel, PwmChannel, PwmTimer, TickTimer, Timer0Pwm, Timer1Pwm, Timer2Pwm,
+};
+
+/// System clock that the timers divide down.
+pub const CLOCK_HZ: u32 = 16_000_000;
+
+/// Division of the 16MHz system clock that drives a timer.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Prescaler {
+    Direct,
+    Div8,
+    Div64,
+    Div256,
+    Div1024,
+}
+
+impl Prescaler {
+    pub const fn divisor(self) -> u32 {
+        match self {
+            Prescaler::Direct => 1,
+            Prescaler::Div8 => 8,
+            Prescaler::Div64 => 64,
+            Prescaler::Div256 => 256,
+            Prescaler::Div1024 => 1024,
+        }
+    }
+}
+
+/// How a timer counts through each period.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Mode {
+    /// Count up to `TOP` and start again from 0. Outputs switch on together at the
+    /// start of each period.
+    Fast,
+    /// Count up to `TOP` and back down, at half the frequency. Each pulse is centred
+    /// in the period, so outputs with different duties never switch at the same
+    /// moment.
+    PhaseCorrect,
+    /// [`Mode::PhaseCorrect`], except that a new `TOP` only takes effect at the bottom
+    /// of the count so the period stays symmetric when the frequency is changed.
+    /// Only differs from phase correct on TC1 with an [`Icr`] resolution.
+    PhaseFrequencyCorrect,
+}
+
+/// How fast a timer counts, how far and in which way, which between them set the
+/// PWM frequency.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Period {
+    pub mode: Mode,
+    pub prescaler: Prescaler,
+    pub top: u16,
+}
+
+impl Period {
+    /// Smallest prescaler that can reach `hz` with ICR1 as `TOP`, which gives the
+    /// finest resolution. Frequencies out of reach are clamped to the nearest one
+    /// that isn't, so check [`Period::frequency`] for what was actually set.
+    pub fn for_frequency(hz: u32, mode: Mode) -> Period {
+        const PRESCALERS: [Prescaler; 5] = [
+            Prescaler::Direct,
+            Prescaler::Div8,
+            Prescaler::Div64,
+            Prescaler::Div256,
+            Prescaler::Div1024,
+        ];
+        let hz = hz.max(1);
+        for &prescaler in PRESCALERS.iter() {
+            let ticks = CLOCK_HZ / prescaler.divisor();
+            let top = match mode {
+                Mode::Fast => ((ticks + hz / 2) / hz).max(1) - 1,
+                _ => (ticks + hz) / (2 * hz),
+            };
+            if top <= u16::MAX as u32 {
+                return Period {
+                    mode,
+                    prescaler,
+                    top: (top as u16).max(MIN_TOP),
+                };
+            }
+        }
+        Period {
+            mode,
+            prescaler: Prescaler::Div1024,
+            top: u16::MAX,
+        }
+    }
+
+    /// Frequency of the output in Hz.
+    pub fn frequency(&self) -> u32 {
+        let ticks = CLOCK_HZ / self.prescaler.divisor();
+        match self.mode {
+            Mode::Fast => ticks / (self.top as u32 + 1),
+            _ => ticks / (2 * self.top as u32),
+        }
+    }
+
+    /// Number of whole bits a duty can be set to.
+    pub fn resolution_bits(&self) -> u8 {
+        (31 - (self.top as u32 + 1).leading_zeros()) as u8
+    }
+}
+
+/// Prescaler and compare value for an 8-bit timer in CTC mode to interrupt as close
+/// to `hz` times a second as it can.
+pub fn ctc_divider(hz: u32) -> (Prescaler, u8) {
+    const PRESCALERS: [Prescaler; 5] = [
+        Prescaler::Direct,
+        Prescaler::Div8,
+        Prescaler::Div64,
+        Prescaler::Div256,
+        Prescaler::Div1024,
+    ];
+    let hz = hz.max(1);
+    for &prescaler in PRESCALERS.iter() {
+        let counts = (CLOCK_HZ / prescaler.divisor() + hz / 2) / hz;
+        if counts <= 0x100 {
+            return (prescaler, (counts.max(1) - 1) as u8);
+        }
+    }
+    (Prescaler::Div1024, u8::MAX)
+}
+
+/// Smallest `TOP` TC1 allows in ICR1, for 2 bits of resolution.
+const MIN_TOP: u16 = 3;
+
+/// Number of steps between off and fully on, as a type so that duties meant for one
+/// resolution can't be written to a channel running at another.
+pub trait Resolution {
+    /// Largest duty, at which the output is high the whole period.
+    const MAX: u16;
+}
+
+/// Resolutions with a fixed `TOP`: 8 bits on any timer, 9 or 10 bits on TC1. These
+/// are the only ones the hardware has, so the trait is sealed.
+pub trait FixedResolution: Resolution + sealed::Sealed {
+    /// Low two WGM1 bits selecting this resolution on TC1.
+    const TIMER1_WGM: u8;
+}
+
+mod sealed {
+    pub trait Sealed {}
+
+    impl Sealed for super::Bits<8> {}
+    impl Sealed for super::Bits<9> {}
+    impl Sealed for super::Bits<10> {}
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Bits<const N: u8>;
+
+impl Resolution for Bits<8> {
+    const MAX: u16 = 0xff;
+}
+
+impl Resolution for Bits<9> {
+    const MAX: u16 = 0x1ff;
+}
+
+impl Resolution for Bits<10> {
+    const MAX: u16 = 0x3ff;
+}
+
+impl FixedResolution for Bits<8> {
+    const TIMER1_WGM: u8 = 0b01;
+}
+
+impl FixedResolution for Bits<9> {
+    const TIMER1_WGM: u8 = 0b10;
+}
+
+impl FixedResolution for Bits<10> {
+    const TIMER1_WGM: u8 = 0b11;
+}
+
+/// TC1 counting up to whatever is in ICR1.
+///
+/// `TOP` is only known at runtime, so duties can be anything up to `u16::MAX` and
+/// are clamped to the timer's `TOP` when they're written.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Icr;
+
+impl Resolution for Icr {
+    const MAX: u16 = u16::MAX;
+}
+
+/// A duty cycle in steps of resolution `R`, never more than `R::MAX`.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Duty<R>(u16, PhantomData<R>);
+
+impl<R: Resolution> Duty<R> {
+    pub const ZERO: Duty<R> = Duty(0, PhantomData);
+    pub const MAX: Duty<R> = Duty(R::MAX, PhantomData);
+
+    /// `None` if `value` doesn't fit in `R`.
+    pub fn new(value: u16) -> Option<Duty<R>> {
+        if value <= R::MAX {
+            Some(Duty(value, PhantomData))
+        } else {
+            None
+        }
+    }
+
+    /// `value`, or [`Duty::MAX`] if it doesn't fit in `R`.
+    pub fn saturating(value: u16) -> Duty<R> {
+        Duty(value.min(R::MAX), PhantomData)
+    }
+
+    /// `value` out of `max`, rescaled to `R`.
+    pub fn scaled(value: u16, max: u16) -> Duty<R> {
+        let value = value.min(max) as u32 * R::MAX as u32 / max.max(1) as u32;
+        Duty(value as u16, PhantomData)
+    }
+}
+
+impl<R> Duty<R> {
+    pub const fn get(self) -> u16 {
+        self.0
+    }
+}
+
+impl<R> From<Duty<R>> for u16 {
+    fn from(duty: Duty<R>) -> u16 {
+        duty.0
+    }
+}
+
+/// What a [`ManagedChannel`] is doing with its pin.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ChannelState {
+    Off,
+    On,
+    Pwm,
+}
+
+/// Which of a timer's two outputs a channel drives.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Output {
+    A,
+    B,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn period_for_frequency() {
+        let lamp = Period::for_frequency(1_000, Mode::Fast);
+        assert_eq!((lamp.prescaler, lamp.top), (Prescaler::Direct, 15_999));
+        assert_eq!(lamp.resolution_bits(), 13);
+
+        let fan = Period::for_frequency(25_000, Mode::Fast);
+        assert_eq!((fan.prescaler, fan.top), (Prescaler::Direct, 639));
+        assert_eq!(fan.frequency(), 25_000);
+        assert_eq!(fan.resolution_bits(), 9);
+
+        let servo = Period::for_frequency(50, Mode::Fast);
+        assert_eq!((servo.prescaler, servo.top), (Prescaler::Div8, 39_999));
+        assert_eq!(servo.frequency(), 50);
+    }
+
+    #[test]
+    fn centred_modes_count_both_ways() {
+        let fan = Period::for_frequency(25_000, Mode::PhaseCorrect);
+        assert_eq!((fan.prescaler, fan.top), (Prescaler::Direct, 320));
+        assert_eq!(fan.frequency(), 25_000);
+        assert_eq!(fan.resolution_bits(), 8);
+
+        let servo = Period::for_frequency(50, Mode::PhaseFrequencyCorrect);
+        assert_eq!((servo.prescaler, servo.top), (Prescaler::Div8, 20_000));
+        assert_eq!(servo.frequency(), 50);
+    }
+
+    #[test]
+    fn unreachable_frequencies_are_clamped() {
+        let fastest = Period::for_frequency(8_000_000, Mode::Fast);
+        assert_eq!(fastest.frequency(), 4_000_000);
+        assert_eq!(fastest.resolution_bits(), 2);
+        assert_eq!(Period::for_frequency(0, Mode::Fast).frequency(), 1);
+        assert_eq!(Period::for_frequency(0, Mode::PhaseCorrect).frequency(), 1);
+    }
+
+    #[test]
+    fn duty_fits_its_resolution() {
+        assert_eq!(Duty::<Bits<8>>::new(255).map(Duty::get), Some(255));
+        assert_eq!(Duty::<Bits<8>>::new(1023), None);
+        assert_eq!(Duty::<Bits<10>>::new(1023).map(Duty::get), Some(1023));
+        assert_eq!(Duty::<Bits<8>>::saturating(1023), Duty::MAX);
+        assert_eq!(Duty::<Icr>::saturating(40_000).get(), 40_000);
+    }
+
+    #[test]
+    fn duty_rescales() {
+        assert_eq!(Duty::<Bits<8>>::scaled(1023, 1023), Duty::MAX);
+        assert_eq!(Duty::<Bits<8>>::scaled(512, 1023).get(), 127);
+        assert_eq!(Duty::<Bits<10>>::scaled(255, 255).get(), 1023);
+        assert_eq!(Duty::<Bits<9>>::scaled(0, 0), Duty::ZERO);
+    }
+
+    #[test]
+    fn divider_for_tick_rate() {
+        assert_eq!(ctc_divider(6_400), (Prescaler::Div64, 38));
+        assert_eq!(ctc_divider(100_000), (Prescaler::Direct, 159));
+    }
+}
@@ -0,0 +1,708 @@
+//! Register level drivers for the three timers.
+
+use super::{
+    ctc_divider, Bits, ChannelState, Duty, FixedResolution, Icr, Mode, Output, Period, Prescaler,
+    Resolution, CLOCK_HZ,
+};
+use crate::curve::Curve;
+use crate::dither::MAX_LEVEL;
+use crate::light::LightOutput;
+use arduino_hal::hal::port::{Dynamic, PB1, PB2, PB3, PD3, PD5, PD6};
+use arduino_hal::pac::{TC0, TC1, TC2};
+use arduino_hal::port::{mode, Pin};
+use avr_device::interrupt;
+use core::marker::PhantomData;
+use embedded_hal::PwmPin;
+
+/// A timer set up for PWM, shared by the channels on its two outputs.
+pub trait PwmTimer {
+    type Resolution: Resolution;
+
+    fn period(&self) -> Period;
+
+    fn counter(&self) -> u16;
+
+    fn compare(&self, output: Output) -> u16;
+
+    fn set_compare(&self, output: Output, value: u16);
+
+    /// Hand the pin to the timer, or back to its port register.
+    fn connect(&self, output: Output, connected: bool);
+
+    /// Fire the timer's overflow interrupt once every period.
+    fn enable_overflow_interrupt(&self);
+
+    fn overflow_interrupt_enabled(&self) -> bool;
+
+    /// Whether the timer has reached `TOP` since [`PwmTimer::clear_overflow`]. The
+    /// overflow interrupt clears this too, so it's only any use while that's off.
+    fn has_overflowed(&self) -> bool;
+
+    fn clear_overflow(&self);
+
+    /// [`PwmTimer::set_compare`] for an interrupt handler, which has no timer to
+    /// borrow.
+    ///
+    /// # Safety
+    ///
+    /// Nothing else may write the same compare register while it's being used.
+    unsafe fn write_compare(output: Output, value: u16);
+}
+
+/// One PWM output pin.
+///
+/// Starts disabled with a duty of 0, so the pin stays low until
+/// [`enable`](PwmChannel::enable) is called, and with a [`Curve::Linear`]
+/// brightness curve.
+pub struct PwmChannel<'t, T: PwmTimer> {
+    timer: &'t T,
+    output: Output,
+    pin: Pin<mode::Output, Dynamic>,
+    curve: Curve,
+}
+
+impl<'t, T: PwmTimer> PwmChannel<'t, T> {
+    fn new(timer: &'t T, output: Output, pin: Pin<mode::Output, Dynamic>) -> PwmChannel<'t, T> {
+        let mut channel = PwmChannel {
+            timer,
+            output,
+            pin,
+            curve: Curve::Linear,
+        };
+        channel.disable();
+        channel.timer.set_compare(output, 0);
+        channel
+    }
+
+    /// Let the timer drive the pin.
+    pub fn enable(&mut self) {
+        self.timer.connect(self.output, true);
+    }
+
+    /// Take the pin away from the timer and hold it low.
+    pub fn disable(&mut self) {
+        self.pin.set_low();
+        self.timer.connect(self.output, false);
+    }
+
+    pub fn duty(&self) -> Duty<T::Resolution> {
+        Duty::saturating(self.timer.compare(self.output))
+    }
+
+    pub(crate) fn timer(&self) -> &'t T {
+        self.timer
+    }
+
+    pub(crate) fn output(&self) -> Output {
+        self.output
+    }
+
+    pub fn max_duty(&self) -> Duty<T::Resolution> {
+        Duty::saturating(self.timer.period().top)
+    }
+
+    /// Set the duty. Only an [`Icr`] timer can have a `TOP` below the largest duty,
+    /// in which case the duty is clamped to it.
+    pub fn set_duty(&mut self, duty: Duty<T::Resolution>) {
+        self.timer
+            .set_compare(self.output, duty.get().min(self.timer.period().top));
+    }
+
+    /// Set the duty to `value` out of `max`, whatever the timer's `TOP` is.
+    pub fn set_scaled(&mut self, value: u16, max: u16) {
+        let top = self.timer.period().top as u32;
+        let duty = value.min(max) as u32 * top / max.max(1) as u32;
+        self.timer.set_compare(self.output, duty as u16);
+    }
+
+    pub fn curve(&self) -> Curve {
+        self.curve
+    }
+
+    /// Curve used by [`set_brightness`](PwmChannel::set_brightness).
+    pub fn set_curve(&mut self, curve: Curve) {
+        self.curve = curve;
+    }
+
+    /// A handle on the channel's compare register for an interrupt handler to write
+    /// to. The channel shouldn't be given a duty itself while it's in use.
+    pub fn compare_writer(&self) -> CompareWriter<T> {
+        CompareWriter {
+            output: self.output,
+            top: self.timer.period().top,
+            timer: PhantomData,
+        }
+    }
+
+    /// Set the duty for a perceived brightness of `value` out of `max`.
+    pub fn set_brightness(&mut self, value: u16, max: u16) {
+        let top = self.timer.period().top;
+        let duty = self.curve.duty(value as u32, max as u32, top);
+        self.timer.set_compare(self.output, duty);
+    }
+}
+
+impl<T: PwmTimer> PwmPin for PwmChannel<'_, T> {
+    type Duty = Duty<T::Resolution>;
+
+    fn disable(&mut self) {
+        PwmChannel::disable(self)
+    }
+
+    fn enable(&mut self) {
+        PwmChannel::enable(self)
+    }
+
+    fn get_duty(&self) -> Self::Duty {
+        self.duty()
+    }
+
+    fn get_max_duty(&self) -> Self::Duty {
+        self.max_duty()
+    }
+
+    fn set_duty(&mut self, duty: Self::Duty) {
+        PwmChannel::set_duty(self, duty)
+    }
+}
+
+impl<T: PwmTimer> LightOutput for PwmChannel<'_, T> {
+    fn set_level(&mut self, level: u16) {
+        self.set_scaled(level, MAX_LEVEL);
+    }
+}
+
+/// Writes a [`PwmChannel`]'s duty without borrowing its timer, from an interrupt
+/// handler.
+pub struct CompareWriter<T> {
+    output: Output,
+    top: u16,
+    timer: PhantomData<fn() -> T>,
+}
+
+impl<T: PwmTimer> LightOutput for CompareWriter<T> {
+    fn set_level(&mut self, level: u16) {
+        let duty = level as u32 * self.top as u32 / MAX_LEVEL as u32;
+        // Safety: the channel leaves its compare register to this writer
+        interrupt::free(|_| unsafe { T::write_compare(self.output, duty as u16) });
+    }
+}
+
+/// A channel that stays on its timer and moves between off, fully on and PWM without
+/// a glitch in between.
+///
+/// Every duty, fully off and fully on included, goes through the compare register,
+/// which the timer only picks up at the end of a period. The exception is off in
+/// [`Mode::Fast`], where a compare of 0 still leaves a spike at the start of each
+/// period. There the pin is taken off the timer and held low instead, but only once
+/// the timer has wrapped, so the pulse in progress is never cut short.
+///
+/// The wrap is found from the timer's overflow flag, which only works with the
+/// timer's overflow interrupt off, as the interrupt clears the flag first. With it
+/// on, as a [`DitherDriver`](crate::dither::DitherDriver) turns it on, the pin is
+/// switched straight away and the pulse in progress can be cut short.
+pub struct ManagedChannel<'t, T: PwmTimer> {
+    channel: PwmChannel<'t, T>,
+    connected: bool,
+}
+
+impl<'t, T: PwmTimer> ManagedChannel<'t, T> {
+    /// Starts off.
+    pub fn new(mut channel: PwmChannel<'t, T>) -> ManagedChannel<'t, T> {
+        channel.disable();
+        channel.set_duty(Duty::ZERO);
+        ManagedChannel {
+            channel,
+            connected: false,
+        }
+    }
+
+    pub fn state(&self) -> ChannelState {
+        let duty = self.channel.duty().get();
+        if !self.connected || duty == 0 {
+            ChannelState::Off
+        } else if duty == self.channel.max_duty().get() {
+            ChannelState::On
+        } else {
+            ChannelState::Pwm
+        }
+    }
+
+    pub fn duty(&self) -> Duty<T::Resolution> {
+        if self.connected {
+            self.channel.duty()
+        } else {
+            Duty::ZERO
+        }
+    }
+
+    pub fn off(&mut self) {
+        self.set(Duty::ZERO);
+    }
+
+    pub fn on(&mut self) {
+        self.set(self.channel.max_duty());
+    }
+
+    /// Change the duty from the next period on. Blocks for up to a period when
+    /// switching on or off in [`Mode::Fast`].
+    pub fn set(&mut self, duty: Duty<T::Resolution>) {
+        let timer = self.channel.timer;
+        let fast = timer.period().mode == Mode::Fast;
+        if duty.get() == 0 && fast {
+            if self.connected {
+                self.channel.set_duty(duty);
+                wait_for_top(timer);
+                self.channel.disable();
+                self.connected = false;
+            }
+            return;
+        }
+
+        self.channel.set_duty(duty);
+        if !self.connected {
+            // Until the new duty is picked up the old one of 0 keeps the output low, so
+            // the first pulse is a whole one. In fast mode that old duty still spikes at
+            // the start of the period, so connect at the end of one instead.
+            if fast {
+                wait_for_top(timer);
+            }
+            self.channel.enable();
+            self.connected = true;
+        }
+    }
+}
+
+impl<T: PwmTimer> LightOutput for ManagedChannel<'_, T> {
+    fn set_level(&mut self, level: u16) {
+        let top = self.channel.max_duty().get() as u32;
+        let duty = level as u32 * top / MAX_LEVEL as u32;
+        self.set(Duty::saturating(duty as u16));
+    }
+}
+
+/// Spin until a timer in [`Mode::Fast`] reaches `TOP`, the last count before it
+/// wraps round and loads new compare values.
+///
+/// Only waits with the overflow interrupt off, and for no longer than a period
+/// takes, so it still returns if the timer has been stopped.
+fn wait_for_top<T: PwmTimer>(timer: &T) {
+    if timer.overflow_interrupt_enabled() {
+        return;
+    }
+    timer.clear_overflow();
+    // Every spin takes at least a cycle, so this many is at least a period
+    let period = timer.period();
+    let spins = (period.top as u32 + 1) * period.prescaler.divisor();
+    for _ in 0..spins {
+        if timer.has_overflowed() {
+            return;
+        }
+    }
+}
+
+/// 8-bit PWM on d6 and d5.
+pub struct Timer0Pwm {
+    tc0: TC0,
+    period: Period,
+}
+
+impl Timer0Pwm {
+    /// [`Mode::PhaseFrequencyCorrect`] is the same as [`Mode::PhaseCorrect`] here, as
+    /// `TOP` never changes.
+    pub fn new(tc0: TC0, mode: Mode, prescaler: Prescaler) -> Timer0Pwm {
+        tc0.tccr0a.write(|w| match mode {
+            Mode::Fast => w.wgm0().bits(0b11),
+            _ => w.wgm0().bits(0b01),
+        });
+        tc0.tccr0b.write(|w| match prescaler {
+            Prescaler::Direct => w.cs0().direct(),
+            Prescaler::Div8 => w.cs0().prescale_8(),
+            Prescaler::Div64 => w.cs0().prescale_64(),
+            Prescaler::Div256 => w.cs0().prescale_256(),
+            Prescaler::Div1024 => w.cs0().prescale_1024(),
+        });
+        let period = Period {
+            mode,
+            prescaler,
+            top: Bits::<8>::MAX,
+        };
+        Timer0Pwm { tc0, period }
+    }
+
+    pub fn d6(&self, pin: Pin<mode::Output, PD6>) -> PwmChannel<'_, Timer0Pwm> {
+        PwmChannel::new(self, Output::A, pin.downgrade())
+    }
+
+    pub fn d5(&self, pin: Pin<mode::Output, PD5>) -> PwmChannel<'_, Timer0Pwm> {
+        PwmChannel::new(self, Output::B, pin.downgrade())
+    }
+}
+
+impl PwmTimer for Timer0Pwm {
+    type Resolution = Bits<8>;
+
+    fn period(&self) -> Period {
+        self.period
+    }
+
+    fn counter(&self) -> u16 {
+        self.tc0.tcnt0.read().bits() as u16
+    }
+
+    fn compare(&self, output: Output) -> u16 {
+        match output {
+            Output::A => self.tc0.ocr0a.read().bits() as u16,
+            Output::B => self.tc0.ocr0b.read().bits() as u16,
+        }
+    }
+
+    fn set_compare(&self, output: Output, value: u16) {
+        match output {
+            Output::A => self.tc0.ocr0a.write(|w| unsafe { w.bits(value as u8) }),
+            Output::B => self.tc0.ocr0b.write(|w| unsafe { w.bits(value as u8) }),
+        }
+    }
+
+    fn connect(&self, output: Output, connected: bool) {
+        self.tc0.tccr0a.modify(|_, w| match (output, connected) {
+            (Output::A, true) => w.com0a().match_clear(),
+            (Output::A, false) => w.com0a().disconnected(),
+            (Output::B, true) => w.com0b().match_clear(),
+            (Output::B, false) => w.com0b().disconnected(),
+        });
+    }
+
+    fn enable_overflow_interrupt(&self) {
+        self.tc0.timsk0.modify(|_, w| w.toie0().set_bit());
+    }
+
+    fn overflow_interrupt_enabled(&self) -> bool {
+        self.tc0.timsk0.read().toie0().bit_is_set()
+    }
+
+    fn has_overflowed(&self) -> bool {
+        self.tc0.tifr0.read().tov0().bit_is_set()
+    }
+
+    fn clear_overflow(&self) {
+        // Flags are cleared by writing a 1 to them
+        self.tc0.tifr0.write(|w| w.tov0().set_bit());
+    }
+
+    unsafe fn write_compare(output: Output, value: u16) {
+        let tc0 = &*TC0::ptr();
+        match output {
+            Output::A => tc0.ocr0a.write(|w| w.bits(value as u8)),
+            Output::B => tc0.ocr0b.write(|w| w.bits(value as u8)),
+        }
+    }
+}
+
+/// PWM on d9 and d10, at 8, 9 or 10 bits or up to a `TOP` in ICR1.
+pub struct Timer1Pwm<R> {
+    tc1: TC1,
+    period: Period,
+    resolution: PhantomData<R>,
+}
+
+impl<R: FixedResolution> Timer1Pwm<R> {
+    /// [`Mode::PhaseFrequencyCorrect`] is the same as [`Mode::PhaseCorrect`] at a
+    /// fixed resolution, as `TOP` never changes.
+    pub fn new(tc1: TC1, mode: Mode, prescaler: Prescaler) -> Timer1Pwm<R> {
+        let wgm = match mode {
+            Mode::Fast => 0b0100 | R::TIMER1_WGM,
+            _ => R::TIMER1_WGM,
+        };
+        let period = Period {
+            mode,
+            prescaler,
+            top: R::MAX,
+        };
+        Timer1Pwm::configure(tc1, wgm, period)
+    }
+}
+
+impl Timer1Pwm<Icr> {
+    /// Count between 0 and `top`, which needs to be at least 3.
+    pub fn with_top(tc1: TC1, mode: Mode, top: u16, prescaler: Prescaler) -> Timer1Pwm<Icr> {
+        let wgm = match mode {
+            Mode::Fast => 0b1110,
+            Mode::PhaseCorrect => 0b1010,
+            Mode::PhaseFrequencyCorrect => 0b1000,
+        };
+        interrupt::free(|_| tc1.icr1.write(|w| unsafe { w.bits(top) }));
+        let period = Period {
+            mode,
+            prescaler,
+            top,
+        };
+        Timer1Pwm::configure(tc1, wgm, period)
+    }
+
+    /// Run as close to `hz` as possible, with as many steps of duty as that allows.
+    ///
+    /// [`PwmTimer::period`] has the frequency and resolution that were set.
+    pub fn with_frequency(tc1: TC1, mode: Mode, hz: u32) -> Timer1Pwm<Icr> {
+        let period = Period::for_frequency(hz, mode);
+        Timer1Pwm::with_top(tc1, mode, period.top, period.prescaler)
+    }
+}
+
+impl<R: Resolution> Timer1Pwm<R> {
+    fn configure(tc1: TC1, wgm: u8, period: Period) -> Timer1Pwm<R> {
+        tc1.tccr1a.write(|w| w.wgm1().bits(wgm & 0b11));
+        tc1.tccr1b.write(|w| {
+            let w = w.wgm1().bits(wgm >> 2);
+            match period.prescaler {
+                Prescaler::Direct => w.cs1().direct(),
+                Prescaler::Div8 => w.cs1().prescale_8(),
+                Prescaler::Div64 => w.cs1().prescale_64(),
+                Prescaler::Div256 => w.cs1().prescale_256(),
+                Prescaler::Div1024 => w.cs1().prescale_1024(),
+            }
+        });
+        Timer1Pwm {
+            tc1,
+            period,
+            resolution: PhantomData,
+        }
+    }
+
+    pub fn d9(&self, pin: Pin<mode::Output, PB1>) -> PwmChannel<'_, Timer1Pwm<R>> {
+        PwmChannel::new(self, Output::A, pin.downgrade())
+    }
+
+    pub fn d10(&self, pin: Pin<mode::Output, PB2>) -> PwmChannel<'_, Timer1Pwm<R>> {
+        PwmChannel::new(self, Output::B, pin.downgrade())
+    }
+}
+
+impl<R: Resolution> PwmTimer for Timer1Pwm<R> {
+    type Resolution = R;
+
+    fn period(&self) -> Period {
+        self.period
+    }
+
+    // The 16-bit registers are read and written a byte at a time through a TEMP
+    // register shared with the interrupt handlers that write compares, so each access
+    // has to be done with interrupts off
+
+    fn counter(&self) -> u16 {
+        interrupt::free(|_| self.tc1.tcnt1.read().bits())
+    }
+
+    fn compare(&self, output: Output) -> u16 {
+        interrupt::free(|_| match output {
+            Output::A => self.tc1.ocr1a.read().bits(),
+            Output::B => self.tc1.ocr1b.read().bits(),
+        })
+    }
+
+    fn set_compare(&self, output: Output, value: u16) {
+        interrupt::free(|_| match output {
+            Output::A => self.tc1.ocr1a.write(|w| unsafe { w.bits(value) }),
+            Output::B => self.tc1.ocr1b.write(|w| unsafe { w.bits(value) }),
+        })
+    }
+
+    fn connect(&self, output: Output, connected: bool) {
+        self.tc1.tccr1a.modify(|_, w| match (output, connected) {
+            (Output::A, true) => w.com1a().match_clear(),
+            (Output::A, false) => w.com1a().disconnected(),
+            (Output::B, true) => w.com1b().match_clear(),
+            (Output::B, false) => w.com1b().disconnected(),
+        });
+    }
+
+    fn enable_overflow_interrupt(&self) {
+        self.tc1.timsk1.modify(|_, w| w.toie1().set_bit());
+    }
+
+    fn overflow_interrupt_enabled(&self) -> bool {
+        self.tc1.timsk1.read().toie1().bit_is_set()
+    }
+
+    fn has_overflowed(&self) -> bool {
+        self.tc1.tifr1.read().tov1().bit_is_set()
+    }
+
+    fn clear_overflow(&self) {
+        // Flags are cleared by writing a 1 to them
+        self.tc1.tifr1.write(|w| w.tov1().set_bit());
+    }
+
+    unsafe fn write_compare(output: Output, value: u16) {
+        let tc1 = &*TC1::ptr();
+        match output {
+            Output::A => tc1.ocr1a.write(|w| w.bits(value)),
+            Output::B => tc1.ocr1b.write(|w| w.bits(value)),
+        }
+    }
+}
+
+/// A timer that only fires its compare A interrupt, at a steady rate, for drivers
+/// that are ticked from an interrupt such as software PWM and fades.
+///
+/// The program defines the interrupt, `TIMER1_COMPA` or `TIMER2_COMPA`, and ticks
+/// every driver that runs off it from there. The timer's PWM pins can't be used for
+/// PWM while it runs.
+pub struct TickTimer<TC> {
+    _tc: TC,
+    rate: u32,
+}
+
+impl TickTimer<TC1> {
+    /// Tick as close to `hz` times a second as TC1 can, which is within a fraction
+    /// of a percent for anything up to a few tens of kHz.
+    pub fn timer1(tc1: TC1, hz: u32) -> TickTimer<TC1> {
+        let period = Period::for_frequency(hz, Mode::Fast);
+        // CTC with OCR1A as `TOP`. OCR1A is written through the TEMP register the
+        // other 16-bit registers share, so an interrupt mustn't get in between
+        interrupt::free(|_| tc1.ocr1a.write(|w| unsafe { w.bits(period.top) }));
+        tc1.tccr1a.write(|w| w.wgm1().bits(0b00));
+        tc1.tccr1b.write(|w| {
+            let w = w.wgm1().bits(0b01);
+            match period.prescaler {
+                Prescaler::Direct => w.cs1().direct(),
+                Prescaler::Div8 => w.cs1().prescale_8(),
+                Prescaler::Div64 => w.cs1().prescale_64(),
+                Prescaler::Div256 => w.cs1().prescale_256(),
+                Prescaler::Div1024 => w.cs1().prescale_1024(),
+            }
+        });
+        tc1.timsk1.write(|w| w.ocie1a().set_bit());
+        TickTimer {
+            _tc: tc1,
+            rate: period.frequency(),
+        }
+    }
+}
+
+impl TickTimer<TC2> {
+    /// Tick as close to `hz` times a second as TC2 can. Being 8-bit, that can be
+    /// several percent out at higher rates, so check [`TickTimer::rate`].
+    pub fn timer2(tc2: TC2, hz: u32) -> TickTimer<TC2> {
+        let (prescaler, compare) = ctc_divider(hz);
+        tc2.tccr2a.write(|w| w.wgm2().bits(0b10));
+        tc2.ocr2a.write(|w| unsafe { w.bits(compare) });
+        tc2.tccr2b.write(|w| match prescaler {
+            Prescaler::Direct => w.cs2().direct(),
+            Prescaler::Div8 => w.cs2().prescale_8(),
+            Prescaler::Div64 => w.cs2().prescale_64(),
+            Prescaler::Div256 => w.cs2().prescale_256(),
+            Prescaler::Div1024 => w.cs2().prescale_1024(),
+        });
+        tc2.timsk2.write(|w| w.ocie2a().set_bit());
+        TickTimer {
+            _tc: tc2,
+            rate: CLOCK_HZ / prescaler.divisor() / (compare as u32 + 1),
+        }
+    }
+}
+
+impl<TC> TickTimer<TC> {
+    /// Ticks a second that were actually set.
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+}
+
+/// 8-bit PWM on d11 and d3.
+pub struct Timer2Pwm {
+    tc2: TC2,
+    period: Period,
+}
+
+impl Timer2Pwm {
+    /// [`Mode::PhaseFrequencyCorrect`] is the same as [`Mode::PhaseCorrect`] here, as
+    /// `TOP` never changes.
+    pub fn new(tc2: TC2, mode: Mode, prescaler: Prescaler) -> Timer2Pwm {
+        tc2.tccr2a.write(|w| match mode {
+            Mode::Fast => w.wgm2().bits(0b11),
+            _ => w.wgm2().bits(0b01),
+        });
+        tc2.tccr2b.write(|w| match prescaler {
+            Prescaler::Direct => w.cs2().direct(),
+            Prescaler::Div8 => w.cs2().prescale_8(),
+            Prescaler::Div64 => w.cs2().prescale_64(),
+            Prescaler::Div256 => w.cs2().prescale_256(),
+            Prescaler::Div1024 => w.cs2().prescale_1024(),
+        });
+        let period = Period {
+            mode,
+            prescaler,
+            top: Bits::<8>::MAX,
+        };
+        Timer2Pwm { tc2, period }
+    }
+
+    pub fn d11(&self, pin: Pin<mode::Output, PB3>) -> PwmChannel<'_, Timer2Pwm> {
+        PwmChannel::new(self, Output::A, pin.downgrade())
+    }
+
+    pub fn d3(&self, pin: Pin<mode::Output, PD3>) -> PwmChannel<'_, Timer2Pwm> {
+        PwmChannel::new(self, Output::B, pin.downgrade())
+    }
+}
+
+impl PwmTimer for Timer2Pwm {
+    type Resolution = Bits<8>;
+
+    fn period(&self) -> Period {
+        self.period
+    }
+
+    fn counter(&self) -> u16 {
+        self.tc2.tcnt2.read().bits() as u16
+    }
+
+    fn compare(&self, output: Output) -> u16 {
+        match output {
+            Output::A => self.tc2.ocr2a.read().bits() as u16,
+            Output::B => self.tc2.ocr2b.read().bits() as u16,
+        }
+    }
+
+    fn set_compare(&self, output: Output, value: u16) {
+        match output {
+            Output::A => self.tc2.ocr2a.write(|w| unsafe { w.bits(value as u8) }),
+            Output::B => self.tc2.ocr2b.write(|w| unsafe { w.bits(value as u8) }),
+        }
+    }
+
+    fn connect(&self, output: Output, connected: bool) {
+        self.tc2.tccr2a.modify(|_, w| match (output, connected) {
+            (Output::A, true) => w.com2a().match_clear(),
+            (Output::A, false) => w.com2a().disconnected(),
+            (Output::B, true) => w.com2b().match_clear(),
+            (Output::B, false) => w.com2b().disconnected(),
+        });
+    }
+
+    fn enable_overflow_interrupt(&self) {
+        self.tc2.timsk2.modify(|_, w| w.toie2().set_bit());
+    }
+
+    fn overflow_interrupt_enabled(&self) -> bool {
+        self.tc2.timsk2.read().toie2().bit_is_set()
+    }
+
+    fn has_overflowed(&self) -> bool {
+        self.tc2.tifr2.read().tov2().bit_is_set()
+    }
+
+    fn clear_overflow(&self) {
+        // Flags are cleared by writing a 1 to them
+        self.tc2.tifr2.write(|w| w.tov2().set_bit());
+    }
+
+    unsafe fn write_compare(output: Output, value: u16) {
+        let tc2 = &*TC2::ptr();
+        match output {
+            Output::A => tc2.ocr2a.write(|w| w.bits(value as u8)),
+            Output::B => tc2.ocr2b.write(|w| w.bits(value as u8)),
+        }
+    }
+}
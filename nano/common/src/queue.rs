@@ -0,0 +1,148 @@
+//! Fixed-capacity queue for passing events from interrupt handlers to the main loop.
+//!
+//! The AVR has no compare-and-swap, so this is a single-producer single-consumer ring
+//! buffer that only needs atomic loads and stores of a byte. Each index is only ever
+//! written from one side: the tail by the producer and the head by the consumer.
+//! Used from just the one side, it is also an ordinary FIFO.
+
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+/// Ring buffer of `N` slots, one of which is always left empty to tell a full queue
+/// from an empty one. `N` has to be from 2 to 256, which is checked at compile time.
+///
+/// [`push`](Queue::push) and [`pop`](Queue::pop) can each only be called from one
+/// context, which can't be interrupted by the other. Usually interrupt handlers push
+/// and the main loop pops: handlers don't interrupt each other on AVR, so several of
+/// them can share one queue. A queue that is only ever pushed and popped from the
+/// main loop is fine too.
+pub struct Queue<T, const N: usize> {
+    buffer: UnsafeCell<MaybeUninit<[T; N]>>,
+    head: AtomicU8,
+    tail: AtomicU8,
+    overflows: AtomicU8,
+}
+
+// Safety: the producer only writes slots the consumer has released and the consumer
+// only reads slots the producer has published, see the docs on `Queue`.
+unsafe impl<T: Send, const N: usize> Sync for Queue<T, N> {}
+
+impl<T, const N: usize> Queue<T, N> {
+    /// Fails to compile when `N` is out of range, as the index is then out of bounds.
+    const SIZE_OK: () = [()][(N < 2 || N > 256) as usize];
+
+    pub const fn new() -> Queue<T, N> {
+        let () = Self::SIZE_OK;
+        Queue {
+            buffer: UnsafeCell::new(MaybeUninit::uninit()),
+            head: AtomicU8::new(0),
+            tail: AtomicU8::new(0),
+            overflows: AtomicU8::new(0),
+        }
+    }
+
+    /// How many values fit in the queue at once.
+    pub const fn capacity(&self) -> usize {
+        N - 1
+    }
+
+    /// Add a value to the back of the queue, or hand it back if the queue is full.
+    pub fn push(&self, value: T) -> Result<(), T> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let next = Self::next(tail);
+        if next == self.head.load(Ordering::Acquire) {
+            let overflows = self.overflows.load(Ordering::Relaxed);
+            self.overflows
+                .store(overflows.saturating_add(1), Ordering::Relaxed);
+            return Err(value);
+        }
+        unsafe { self.slot(tail).write(value) };
+        self.tail.store(next, Ordering::Release);
+        Ok(())
+    }
+
+    /// Take the value at the front of the queue.
+    pub fn pop(&self) -> Option<T> {
+        let head = self.head.load(Ordering::Relaxed);
+        if head == self.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        let value = unsafe { self.slot(head).read() };
+        self.head.store(Self::next(head), Ordering::Release);
+        Some(value)
+    }
+
+    pub fn len(&self) -> usize {
+        let head = self.head.load(Ordering::Acquire) as usize;
+        let tail = self.tail.load(Ordering::Acquire) as usize;
+        (tail + N - head) % N
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of values dropped because the queue was full, saturating at 255.
+    pub fn overflows(&self) -> u8 {
+        self.overflows.load(Ordering::Relaxed)
+    }
+
+    fn next(index: u8) -> u8 {
+        if index as usize + 1 == N {
+            0
+        } else {
+            index + 1
+        }
+    }
+
+    fn slot(&self, index: u8) -> *mut T {
+        unsafe { (self.buffer.get() as *mut T).add(index as usize) }
+    }
+}
+
+impl<T, const N: usize> Default for Queue<T, N> {
+    fn default() -> Queue<T, N> {
+        Queue::new()
+    }
+}
+
+impl<T, const N: usize> Drop for Queue<T, N> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_in_first_out_across_wrap() {
+        let queue: Queue<u16, 4> = Queue::new();
+        for round in 0..10 {
+            assert!(queue.push(round).is_ok());
+            assert!(queue.push(round + 100).is_ok());
+            assert_eq!(queue.len(), 2);
+            assert_eq!(queue.pop(), Some(round));
+            assert_eq!(queue.pop(), Some(round + 100));
+            assert_eq!(queue.pop(), None);
+        }
+        assert_eq!(queue.overflows(), 0);
+    }
+
+    #[test]
+    fn full_queue_counts_overflows() {
+        let queue: Queue<u8, 4> = Queue::new();
+        assert_eq!(queue.capacity(), 3);
+        for i in 0..5 {
+            let _ = queue.push(i);
+        }
+        assert_eq!(queue.overflows(), 2);
+        assert_eq!(queue.push(9), Err(9));
+        assert_eq!(queue.pop(), Some(0));
+        assert_eq!(queue.pop(), Some(1));
+        assert_eq!(queue.pop(), Some(2));
+        assert!(queue.is_empty());
+    }
+}
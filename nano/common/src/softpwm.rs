@@ -0,0 +1,261 @@
+//! PWM in software, for pins that have no timer output or more channels than the
+//! timers have.
+//!
+//! A timer interrupt fires `steps` times per period and calls
+//! [`SoftPwmDriver::tick`]. Each tick raises or lowers whole groups of pins with a
+//! single write per port: every pin with a duty goes high at the start of the period,
+//! and each one goes low again on the step its duty runs out.
+//!
+//! The main loop never touches that state directly. Changing a duty builds a new
+//! [`Schedule`], which the interrupt only swaps in at the start of a period, so a
+//! period is never cut short or run twice.
+//!
+//! The interrupt writes each port with a read-modify-write, so the main loop has to
+//! write other pins on the same ports inside a critical section. Otherwise a tick
+//! between its read and write is undone, which lasts until the interrupt next
+//! writes those PWM pins, at most a period later.
+
+#[cfg(target_arch = "avr")]
+mod driver;
+
+#[cfg(target_arch = "avr")]
+pub use driver::{SoftPwmDriver, SoftPwmPin, SoftPwmPins};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Port {
+    B,
+    C,
+    D,
+}
+
+/// Where a pin's output bit lives.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PortPin {
+    pub port: Port,
+    pub mask: u8,
+}
+
+/// A set of bits on each port.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct PortMasks {
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+}
+
+impl PortMasks {
+    pub const NONE: PortMasks = PortMasks { b: 0, c: 0, d: 0 };
+
+    pub fn insert(&mut self, pin: PortPin) {
+        match pin.port {
+            Port::B => self.b |= pin.mask,
+            Port::C => self.c |= pin.mask,
+            Port::D => self.d |= pin.mask,
+        }
+    }
+
+    pub fn contains(&self, pin: PortPin) -> bool {
+        let bits = match pin.port {
+            Port::B => self.b,
+            Port::C => self.c,
+            Port::D => self.d,
+        };
+        bits & pin.mask == pin.mask
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == PortMasks::NONE
+    }
+}
+
+/// What to write at a tick: `set` goes high, then `clear` goes low.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct PortWrites {
+    pub set: PortMasks,
+    pub clear: PortMasks,
+}
+
+/// One period's worth of pin changes for `N` pins.
+#[derive(Clone, Copy, Debug)]
+pub struct Schedule<const N: usize> {
+    start: PortWrites,
+    /// Step each group of pins goes low at, in order, with `len` of them in use.
+    ends: [(u16, PortMasks); N],
+    len: usize,
+}
+
+impl<const N: usize> Schedule<N> {
+    /// Every pin held low.
+    pub const fn new() -> Schedule<N> {
+        Schedule {
+            start: PortWrites {
+                set: PortMasks::NONE,
+                clear: PortMasks::NONE,
+            },
+            ends: [(0, PortMasks::NONE); N],
+            len: 0,
+        }
+    }
+
+    /// Duties run from 0 for always low to `steps` for always high.
+    pub fn build(pins: &[PortPin; N], duties: &[u16; N], steps: u16) -> Schedule<N> {
+        let mut schedule = Schedule::new();
+        for (&pin, &duty) in pins.iter().zip(duties.iter()) {
+            let duty = duty.min(steps);
+            if duty == 0 {
+                schedule.start.clear.insert(pin);
+                continue;
+            }
+            schedule.start.set.insert(pin);
+            if duty < steps {
+                schedule.end_at(duty, pin);
+            }
+        }
+        schedule
+    }
+
+    fn end_at(&mut self, step: u16, pin: PortPin) {
+        let ends = &mut self.ends[..self.len];
+        let index = match ends.binary_search_by_key(&step, |&(at, _)| at) {
+            Ok(index) => {
+                ends[index].1.insert(pin);
+                return;
+            }
+            Err(index) => index,
+        };
+        let mut masks = PortMasks::NONE;
+        masks.insert(pin);
+        self.ends.copy_within(index..self.len, index + 1);
+        self.ends[index] = (step, masks);
+        self.len += 1;
+    }
+}
+
+impl<const N: usize> Default for Schedule<N> {
+    fn default() -> Schedule<N> {
+        Schedule::new()
+    }
+}
+
+/// The interrupt's side: steps through the active schedule one tick at a time.
+pub struct SoftPwm<const N: usize> {
+    steps: u16,
+    step: u16,
+    next: usize,
+    active: Schedule<N>,
+    pending: Option<Schedule<N>>,
+}
+
+impl<const N: usize> SoftPwm<N> {
+    pub const fn new(steps: u16) -> SoftPwm<N> {
+        SoftPwm {
+            steps,
+            step: 0,
+            next: 0,
+            active: Schedule::new(),
+            pending: None,
+        }
+    }
+
+    pub fn steps(&self) -> u16 {
+        self.steps
+    }
+
+    /// Use `schedule` from the start of the next period.
+    pub fn set_schedule(&mut self, schedule: Schedule<N>) {
+        self.pending = Some(schedule);
+    }
+
+    /// Advance one step and return the pins to change.
+    pub fn tick(&mut self) -> PortWrites {
+        let mut writes = PortWrites::default();
+        if self.step == 0 {
+            if let Some(schedule) = self.pending.take() {
+                self.active = schedule;
+            }
+            self.next = 0;
+            writes = self.active.start;
+        }
+        let ends = &self.active.ends[..self.active.len];
+        if let Some(&(at, masks)) = ends.get(self.next) {
+            if at == self.step {
+                writes.clear = masks;
+                self.next += 1;
+            }
+        }
+        self.step += 1;
+        if self.step >= self.steps {
+            self.step = 0;
+        }
+        writes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PINS: [PortPin; 3] = [
+        PortPin {
+            port: Port::B,
+            mask: 1 << 1,
+        },
+        PortPin {
+            port: Port::B,
+            mask: 1 << 2,
+        },
+        PortPin {
+            port: Port::D,
+            mask: 1 << 7,
+        },
+    ];
+
+    /// Number of steps each pin spent high over one period.
+    fn run_period(pwm: &mut SoftPwm<3>) -> [u16; 3] {
+        let mut ports = PortMasks::NONE;
+        let mut high = [0; 3];
+        for _ in 0..pwm.steps() {
+            let writes = pwm.tick();
+            ports.b = (ports.b | writes.set.b) & !writes.clear.b;
+            ports.c = (ports.c | writes.set.c) & !writes.clear.c;
+            ports.d = (ports.d | writes.set.d) & !writes.clear.d;
+            for (pin, high) in PINS.iter().zip(high.iter_mut()) {
+                *high += ports.contains(*pin) as u16;
+            }
+        }
+        high
+    }
+
+    #[test]
+    fn pins_are_high_for_their_duty() {
+        let mut pwm = SoftPwm::new(64);
+        pwm.set_schedule(Schedule::build(&PINS, &[16, 40, 64], 64));
+        assert_eq!(run_period(&mut pwm), [16, 40, 64]);
+        assert_eq!(run_period(&mut pwm), [16, 40, 64]);
+
+        pwm.set_schedule(Schedule::build(&PINS, &[0, 100, 1], 64));
+        assert_eq!(run_period(&mut pwm), [0, 64, 1]);
+    }
+
+    #[test]
+    fn pins_ending_together_are_written_together() {
+        let schedule = Schedule::build(&PINS, &[10, 10, 10], 64);
+        assert_eq!(schedule.len, 1);
+        assert_eq!(schedule.ends[0].1.b, 0b110);
+        assert_eq!(schedule.ends[0].1.d, 1 << 7);
+    }
+
+    #[test]
+    fn new_schedule_waits_for_the_period_to_end() {
+        let mut pwm = SoftPwm::new(8);
+        pwm.set_schedule(Schedule::build(&PINS, &[4, 4, 4], 8));
+        pwm.tick();
+        pwm.set_schedule(Schedule::build(&PINS, &[8, 8, 8], 8));
+        let mut cleared = false;
+        for _ in 1..8 {
+            cleared |= pwm.tick().clear.contains(PINS[0]);
+        }
+        assert!(cleared);
+        assert_eq!(run_period(&mut pwm), [8, 8, 8]);
+    }
+}
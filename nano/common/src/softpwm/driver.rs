@@ -0,0 +1,200 @@
+//! Timer and port access for software PWM.
+
+use super::{Port, PortPin, PortWrites, Schedule, SoftPwm};
+use crate::dither::MAX_LEVEL;
+use crate::light::Outputs;
+use arduino_hal::hal::port::{
+    Dynamic, PB0, PB1, PB2, PB3, PB4, PB5, PC0, PC1, PC2, PC3, PC4, PC5, PD0, PD1, PD2, PD3, PD4,
+    PD5, PD6, PD7,
+};
+use arduino_hal::pac::{PORTB, PORTC, PORTD};
+use arduino_hal::port::{mode, Pin};
+use avr_device::interrupt::{self, Mutex};
+use core::cell::RefCell;
+
+/// Software PWM state shared with the timer interrupt. Meant to live in a `static`.
+///
+/// The interrupt writes the PWM pins with a read-modify-write of their whole port.
+/// Anything the main program writes to other pins on the same ports has to be done
+/// inside `interrupt::free`, or a tick landing in the middle of a read-modify-write
+/// has its change to the PWM pins undone.
+pub struct SoftPwmDriver<const N: usize> {
+    pwm: Mutex<RefCell<SoftPwm<N>>>,
+}
+
+impl<const N: usize> SoftPwmDriver<N> {
+    /// `steps` is the resolution: duties go from 0 to `steps`.
+    pub const fn new(steps: u16) -> SoftPwmDriver<N> {
+        SoftPwmDriver {
+            pwm: Mutex::new(RefCell::new(SoftPwm::new(steps))),
+        }
+    }
+
+    /// Ticks a second needed for `frequency` whole periods a second, to start a
+    /// [`TickTimer`](crate::pwm::TickTimer) or any other interrupt at.
+    pub fn tick_rate(&self, frequency: u32) -> u32 {
+        frequency * self.steps() as u32
+    }
+
+    /// Advance one step. Call from a timer interrupt, [`tick_rate`] times a second.
+    ///
+    /// [`tick_rate`]: SoftPwmDriver::tick_rate
+    pub fn tick(&self) {
+        interrupt::free(|cs| {
+            let writes = self.pwm.borrow(cs).borrow_mut().tick();
+            apply(writes);
+        });
+    }
+
+    fn set_schedule(&self, schedule: Schedule<N>) {
+        interrupt::free(|cs| self.pwm.borrow(cs).borrow_mut().set_schedule(schedule));
+    }
+
+    fn steps(&self) -> u16 {
+        interrupt::free(|cs| self.pwm.borrow(cs).borrow().steps())
+    }
+}
+
+/// An output pin for a [`SoftPwmPins`], along with the port and bit it writes to.
+///
+/// Made from any of the nano's typed output pins with `into`. `Dynamic` pins don't
+/// say which they are, so their port and bit have to be given with
+/// [`SoftPwmPin::from_dynamic`].
+pub struct SoftPwmPin {
+    _pin: Pin<mode::Output, Dynamic>,
+    port_pin: PortPin,
+}
+
+impl SoftPwmPin {
+    /// `pin`, which is bit `bit` of `port`.
+    ///
+    /// # Safety
+    ///
+    /// `port` and `bit` have to be `pin`'s, or the interrupt drives some other pin
+    /// instead, whoever owns it.
+    pub unsafe fn from_dynamic(pin: Pin<mode::Output, Dynamic>, port: Port, bit: u8) -> SoftPwmPin {
+        SoftPwmPin {
+            _pin: pin,
+            port_pin: PortPin {
+                port,
+                mask: 1 << (bit & 7),
+            },
+        }
+    }
+}
+
+macro_rules! soft_pwm_pins {
+    ($($pin:ident: $port:ident $bit:literal,)*) => {
+        $(
+            impl From<Pin<mode::Output, $pin>> for SoftPwmPin {
+                fn from(pin: Pin<mode::Output, $pin>) -> SoftPwmPin {
+                    SoftPwmPin {
+                        _pin: pin.downgrade(),
+                        port_pin: PortPin {
+                            port: Port::$port,
+                            mask: 1 << $bit,
+                        },
+                    }
+                }
+            }
+        )*
+    };
+}
+
+soft_pwm_pins! {
+    PB0: B 0, PB1: B 1, PB2: B 2, PB3: B 3, PB4: B 4, PB5: B 5,
+    PC0: C 0, PC1: C 1, PC2: C 2, PC3: C 3, PC4: C 4, PC5: C 5,
+    PD0: D 0, PD1: D 1, PD2: D 2, PD3: D 3, PD4: D 4, PD5: D 5, PD6: D 6, PD7: D 7,
+}
+
+/// The pins driven by a [`SoftPwmDriver`], and their duties.
+pub struct SoftPwmPins<const N: usize> {
+    driver: &'static SoftPwmDriver<N>,
+    _pins: [SoftPwmPin; N],
+    port_pins: [PortPin; N],
+    duties: [u16; N],
+}
+
+impl<const N: usize> SoftPwmPins<N> {
+    /// Take over `pins`, all starting with a duty of 0.
+    pub fn new(driver: &'static SoftPwmDriver<N>, pins: [SoftPwmPin; N]) -> SoftPwmPins<N> {
+        let mut port_pins = [PortPin {
+            port: Port::B,
+            mask: 0,
+        }; N];
+        for (port_pin, pin) in port_pins.iter_mut().zip(pins.iter()) {
+            *port_pin = pin.port_pin;
+        }
+        let duties = [0; N];
+        driver.set_schedule(Schedule::build(&port_pins, &duties, driver.steps()));
+        SoftPwmPins {
+            driver,
+            _pins: pins,
+            port_pins,
+            duties,
+        }
+    }
+
+    pub fn max_duty(&self) -> u16 {
+        self.driver.steps()
+    }
+
+    pub fn duty(&self, index: usize) -> u16 {
+        self.duties[index]
+    }
+
+    /// Set the duty of the pin at `index`, clamped to [`max_duty`], from the next
+    /// period on.
+    ///
+    /// [`max_duty`]: SoftPwmPins::max_duty
+    pub fn set_duty(&mut self, index: usize, duty: u16) {
+        self.set_duties(|duties| duties[index] = duty);
+    }
+
+    /// Change any number of duties at once, so they all take effect in the same
+    /// period.
+    pub fn set_duties(&mut self, f: impl FnOnce(&mut [u16; N])) {
+        let steps = self.driver.steps();
+        f(&mut self.duties);
+        for duty in self.duties.iter_mut() {
+            *duty = (*duty).min(steps);
+        }
+        self.driver
+            .set_schedule(Schedule::build(&self.port_pins, &self.duties, steps));
+    }
+}
+
+/// Every channel changes in the same period.
+impl<const N: usize> Outputs<N> for SoftPwmPins<N> {
+    fn set_levels(&mut self, levels: &[u16; N]) {
+        let steps = self.max_duty() as u32;
+        self.set_duties(|duties| {
+            for (duty, &level) in duties.iter_mut().zip(levels.iter()) {
+                *duty = (level as u32 * steps / MAX_LEVEL as u32) as u16;
+            }
+        });
+    }
+}
+
+fn apply(writes: PortWrites) {
+    let PortWrites { set, clear } = writes;
+    // Safety: called from the interrupt with interrupts disabled, and only changes
+    // the bits of pins owned by a `SoftPwmPins`
+    unsafe {
+        if set.b | clear.b != 0 {
+            (*PORTB::ptr())
+                .portb
+                .modify(|r, w| w.bits((r.bits() | set.b) & !clear.b));
+        }
+        if set.c | clear.c != 0 {
+            (*PORTC::ptr())
+                .portc
+                .modify(|r, w| w.bits((r.bits() | set.c) & !clear.c));
+        }
+        if set.d | clear.d != 0 {
+            (*PORTD::ptr())
+                .portd
+                .modify(|r, w| w.bits((r.bits() | set.d) & !clear.d));
+        }
+    }
+}
@@ -0,0 +1,414 @@
+//! Status codes blinked out on a single LED.
+//!
+//! A [`StatusLight`] plays [`Pattern`]s on any output pin without blocking: patterns
+//! are queued, and each call to [`StatusLight::update`] moves the pin on to whatever
+//! it should be doing by now. With nothing queued it plays its idle pattern, if it
+//! has one, so a heartbeat can show the firmware is still running between codes.
+//!
+//! Everything is timed in units, with the usual Morse spacing: a short blink or dot
+//! is one unit on, a long blink or dash is three, with one unit off in between. Letters
+//! are three units apart and words seven, and a seven unit gap follows every pattern.
+
+use crate::queue::Queue;
+use crate::time::{Clock, Duration, Instant};
+use embedded_hal::digital::v2::OutputPin;
+
+/// Length of a unit if none is given.
+pub const DEFAULT_UNIT: Duration = Duration::from_millis(150);
+
+/// Units off after each pattern.
+const GAP: u8 = 7;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Pattern {
+    /// `short` short blinks then `long` long ones.
+    Blinks { short: u8, long: u8 },
+    /// A message in Morse code. Anything but letters, digits and spaces is skipped.
+    Morse(&'static str),
+    /// Two quick blinks.
+    Heartbeat,
+}
+
+/// The pin's state for a number of units.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Step {
+    on: bool,
+    units: u8,
+}
+
+impl Step {
+    fn on(units: u8) -> Option<Step> {
+        Some(Step { on: true, units })
+    }
+
+    fn off(units: u8) -> Option<Step> {
+        Some(Step { on: false, units })
+    }
+}
+
+/// Where a pattern has got to.
+struct Steps {
+    pattern: Pattern,
+    /// Blinks given so far.
+    blinks: u8,
+    /// Morse still to send, and the rest of the letter being sent.
+    text: &'static str,
+    code: &'static str,
+    /// Units off owed before the next dot or dash, if there is one.
+    gap: u8,
+    off: Option<u8>,
+    finished: bool,
+}
+
+impl Steps {
+    fn new(pattern: Pattern) -> Steps {
+        Steps {
+            pattern,
+            blinks: 0,
+            text: match pattern {
+                Pattern::Morse(text) => text,
+                _ => "",
+            },
+            code: "",
+            gap: 0,
+            off: None,
+            finished: false,
+        }
+    }
+
+    fn next(&mut self) -> Option<Step> {
+        if let Some(units) = self.off.take() {
+            return Step::off(units);
+        }
+        if self.finished {
+            return None;
+        }
+        let (short, long) = match self.pattern {
+            Pattern::Blinks { short, long } => (short, long),
+            Pattern::Heartbeat => (2, 0),
+            Pattern::Morse(_) => return self.next_morse(),
+        };
+        let total = short.saturating_add(long);
+        if self.blinks == total {
+            self.finished = true;
+            return Step::off(GAP);
+        }
+        self.blinks += 1;
+        if self.blinks < total {
+            self.off = Some(1);
+        }
+        Step::on(if self.blinks <= short { 1 } else { 3 })
+    }
+
+    fn next_morse(&mut self) -> Option<Step> {
+        loop {
+            if let Some(symbol) = self.code.bytes().next() {
+                // Gaps are only decided once there's something to send after them, so
+                // skipped characters and trailing spaces don't add any
+                if self.gap > 0 {
+                    let gap = self.gap;
+                    self.gap = 0;
+                    return Step::off(gap);
+                }
+                self.code = &self.code[1..];
+                self.gap = if self.code.is_empty() { 3 } else { 1 };
+                return Step::on(if symbol == b'.' { 1 } else { 3 });
+            }
+            let mut chars = self.text.chars();
+            let c = chars.next();
+            self.text = chars.as_str();
+            match c {
+                None => {
+                    self.finished = true;
+                    return Step::off(GAP);
+                }
+                Some(' ') if self.gap > 0 => self.gap = 7,
+                Some(c) => self.code = morse(c),
+            }
+        }
+    }
+}
+
+/// Dots and dashes for `c`, or nothing if it has no code.
+fn morse(c: char) -> &'static str {
+    match c.to_ascii_uppercase() {
+        'A' => ".-",
+        'B' => "-...",
+        'C' => "-.-.",
+        'D' => "-..",
+        'E' => ".",
+        'F' => "..-.",
+        'G' => "--.",
+        'H' => "....",
+        'I' => "..",
+        'J' => ".---",
+        'K' => "-.-",
+        'L' => ".-..",
+        'M' => "--",
+        'N' => "-.",
+        'O' => "---",
+        'P' => ".--.",
+        'Q' => "--.-",
+        'R' => ".-.",
+        'S' => "...",
+        'T' => "-",
+        'U' => "..-",
+        'V' => "...-",
+        'W' => ".--",
+        'X' => "-..-",
+        'Y' => "-.--",
+        'Z' => "--..",
+        '0' => "-----",
+        '1' => ".----",
+        '2' => "..---",
+        '3' => "...--",
+        '4' => "....-",
+        '5' => ".....",
+        '6' => "-....",
+        '7' => "--...",
+        '8' => "---..",
+        '9' => "----.",
+        _ => "",
+    }
+}
+
+/// Plays queued [`Pattern`]s on an LED. Up to `N - 1` patterns can wait their turn.
+pub struct StatusLight<P, C, const N: usize> {
+    pin: P,
+    clock: C,
+    unit: Duration,
+    idle: Option<Pattern>,
+    queue: Queue<Pattern, N>,
+    steps: Option<Steps>,
+    /// Whether `steps` is a queued pattern rather than the idle one.
+    queued: bool,
+    step_end: Instant,
+}
+
+impl<P: OutputPin, C: Clock, const N: usize> StatusLight<P, C, N> {
+    /// Status light on a pin that lights the LED when high. Nothing plays until the
+    /// first [`StatusLight::update`].
+    pub fn new(pin: P, clock: C) -> StatusLight<P, C, N> {
+        let now = clock.now();
+        StatusLight {
+            pin,
+            clock,
+            unit: DEFAULT_UNIT,
+            idle: None,
+            queue: Queue::new(),
+            steps: None,
+            queued: false,
+            step_end: now,
+        }
+    }
+
+    pub fn with_unit(mut self, unit: Duration) -> Self {
+        self.unit = unit;
+        self
+    }
+
+    /// Pattern to play over and over while nothing is queued.
+    pub fn with_idle(mut self, pattern: Pattern) -> Self {
+        self.idle = Some(pattern);
+        self
+    }
+
+    /// Queue `pattern` to play after the ones already waiting, or hand it back if
+    /// the queue is full.
+    pub fn play(&mut self, pattern: Pattern) -> Result<(), Pattern> {
+        self.queue.push(pattern)
+    }
+
+    /// Whether a queued pattern is playing or waiting, even one that's the same as
+    /// the idle pattern.
+    pub fn is_busy(&self) -> bool {
+        self.queued || !self.queue.is_empty()
+    }
+
+    /// Move the pin on. Call this regularly, ideally a good deal more often than
+    /// once a unit.
+    pub fn update(&mut self) -> Result<(), P::Error> {
+        let now = self.clock.now();
+        if now.is_before(self.step_end) {
+            return Ok(());
+        }
+        // Steps follow on from where the last one should have ended, so the time it
+        // takes to get round to calling this doesn't add up. After a pause, or if
+        // this hasn't been called for a while, they start from now instead.
+        let start = if self.steps.is_none() || now.since(self.step_end) > self.unit {
+            now
+        } else {
+            self.step_end
+        };
+        // Every pattern ends with a gap, so this always finds a step unless there's
+        // nothing to play
+        let step = loop {
+            if let Some(step) = self.steps.as_mut().and_then(Steps::next) {
+                break step;
+            }
+            let pattern = match self.queue.pop() {
+                Some(pattern) => {
+                    self.queued = true;
+                    Some(pattern)
+                }
+                None => {
+                    self.queued = false;
+                    self.idle
+                }
+            };
+            match pattern {
+                Some(pattern) => self.steps = Some(Steps::new(pattern)),
+                None => {
+                    self.steps = None;
+                    self.step_end = now;
+                    return self.pin.set_low();
+                }
+            }
+        };
+        self.step_end = start + Duration::from_millis(self.unit.as_millis() * step.units as u32);
+        if step.on {
+            self.pin.set_high()
+        } else {
+            self.pin.set_low()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+    use core::convert::Infallible;
+
+    struct FakeClock(Cell<u32>);
+
+    impl Clock for FakeClock {
+        fn now(&self) -> Instant {
+            Instant::from_millis(self.0.get())
+        }
+    }
+
+    struct FakePin<'a>(&'a Cell<bool>);
+
+    impl OutputPin for FakePin<'_> {
+        type Error = Infallible;
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            self.0.set(true);
+            Ok(())
+        }
+
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            self.0.set(false);
+            Ok(())
+        }
+    }
+
+    /// Run a light with a 1ms unit for `millis` ms, with `patterns` queued and `idle`
+    /// as its idle pattern. Returns the LED for each ms, `#` for on and `.` for off.
+    fn play(patterns: &[Pattern], idle: Option<Pattern>, millis: u32) -> String {
+        let clock = FakeClock(Cell::new(0));
+        let led = Cell::new(false);
+        let mut light: StatusLight<_, _, 4> =
+            StatusLight::new(FakePin(&led), &clock).with_unit(Duration::from_millis(1));
+        if let Some(idle) = idle {
+            light = light.with_idle(idle);
+        }
+        for &pattern in patterns {
+            light.play(pattern).unwrap();
+        }
+        let mut shown = String::new();
+        for millis in 0..millis {
+            clock.0.set(millis);
+            light.update().unwrap();
+            shown.push(if led.get() { '#' } else { '.' });
+        }
+        shown
+    }
+
+    #[test]
+    fn blink_codes_play_in_turn() {
+        let patterns = [
+            Pattern::Blinks { short: 2, long: 1 },
+            Pattern::Blinks { short: 0, long: 2 },
+        ];
+        assert_eq!(play(&patterns, None, 30), "#.#.###.......###.###.........",);
+    }
+
+    #[test]
+    fn morse_spacing() {
+        assert_eq!(
+            play(&[Pattern::Morse("e t")], None, 20),
+            "#.......###.........",
+        );
+        assert_eq!(
+            play(&[Pattern::Morse("Sos")], None, 34),
+            "#.#.#...###.###.###...#.#.#.......",
+        );
+        // Characters without a code don't leave gaps, wherever they are
+        assert_eq!(
+            play(&[Pattern::Morse("?e?t? ")], None, 14),
+            "#...###.......",
+        );
+    }
+
+    #[test]
+    fn slow_polling_doesnt_drift() {
+        let clock = FakeClock(Cell::new(0));
+        let led = Cell::new(false);
+        let mut light: StatusLight<_, _, 4> =
+            StatusLight::new(FakePin(&led), &clock).with_unit(Duration::from_millis(10));
+        light.play(Pattern::Blinks { short: 3, long: 0 }).unwrap();
+        let mut changes = Vec::new();
+        let mut last = false;
+        for millis in (0..60).step_by(3) {
+            clock.0.set(millis);
+            light.update().unwrap();
+            if led.get() != last {
+                last = led.get();
+                changes.push(millis);
+            }
+        }
+        assert_eq!(changes, [0, 12, 21, 30, 42, 51]);
+    }
+
+    #[test]
+    fn idle_pattern_fills_the_gaps() {
+        let clock = FakeClock(Cell::new(0));
+        let led = Cell::new(false);
+        let mut light: StatusLight<_, _, 4> = StatusLight::new(FakePin(&led), &clock)
+            .with_unit(Duration::from_millis(1))
+            .with_idle(Pattern::Heartbeat);
+        assert!(!light.is_busy());
+        light.play(Pattern::Morse("t")).unwrap();
+        assert!(light.is_busy());
+        for millis in 0..=10 {
+            clock.0.set(millis);
+            light.update().unwrap();
+        }
+        assert!(!light.is_busy());
+
+        assert_eq!(
+            play(&[Pattern::Morse("t")], Some(Pattern::Heartbeat), 30),
+            "###.......#.#.......#.#.......",
+        );
+    }
+
+    #[test]
+    fn idle_pattern_played_as_a_code_is_busy() {
+        let clock = FakeClock(Cell::new(0));
+        let led = Cell::new(false);
+        let mut light: StatusLight<_, _, 4> = StatusLight::new(FakePin(&led), &clock)
+            .with_unit(Duration::from_millis(1))
+            .with_idle(Pattern::Heartbeat);
+        light.play(Pattern::Heartbeat).unwrap();
+        for millis in 0..10 {
+            clock.0.set(millis);
+            light.update().unwrap();
+            assert!(light.is_busy());
+        }
+        clock.0.set(10);
+        light.update().unwrap();
+        assert!(!light.is_busy());
+    }
+}
@@ -0,0 +1,123 @@
+//! Wrap-safe points in time and spans of time on the millisecond clock.
+//!
+//! The millis counter is a `u32` and wraps after ~49 days, so instants can't simply be
+//! subtracted or compared with `<`. All arithmetic here wraps and comparisons look at
+//! the wrapped difference instead, which is correct as long as the two instants are
+//! less than ~24 days apart.
+
+use core::ops::{Add, AddAssign, Sub, SubAssign};
+
+/// A point in time, in milliseconds since the clock started.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Instant(u32);
+
+/// A span of time in milliseconds.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Default)]
+pub struct Duration(u32);
+
+/// Source of the current time.
+///
+/// On the chip this is `millis::Millis`; tests use a fake clock they
+/// can step by hand.
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+impl<C: Clock> Clock for &C {
+    fn now(&self) -> Instant {
+        (**self).now()
+    }
+}
+
+impl Instant {
+    pub const fn from_millis(millis: u32) -> Instant {
+        Instant(millis)
+    }
+
+    pub const fn as_millis(self) -> u32 {
+        self.0
+    }
+
+    /// Time from `earlier` to `self`, correct across a counter wrap.
+    pub fn since(self, earlier: Instant) -> Duration {
+        Duration(self.0.wrapping_sub(earlier.0))
+    }
+
+    /// Whether `self` comes strictly after `other`.
+    pub fn is_after(self, other: Instant) -> bool {
+        (self.0.wrapping_sub(other.0) as i32) > 0
+    }
+
+    /// Whether `self` comes strictly before `other`.
+    pub fn is_before(self, other: Instant) -> bool {
+        other.is_after(self)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Instant {
+        Instant(self.0.wrapping_add(rhs.0))
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, rhs: Duration) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, rhs: Duration) -> Instant {
+        Instant(self.0.wrapping_sub(rhs.0))
+    }
+}
+
+impl SubAssign<Duration> for Instant {
+    fn sub_assign(&mut self, rhs: Duration) {
+        *self = *self - rhs;
+    }
+}
+
+impl Duration {
+    pub const ZERO: Duration = Duration(0);
+    pub const MAX: Duration = Duration(u32::MAX);
+
+    pub const fn from_millis(millis: u32) -> Duration {
+        Duration(millis)
+    }
+
+    pub const fn from_secs(secs: u32) -> Duration {
+        Duration(secs * 1000)
+    }
+
+    pub const fn as_millis(self) -> u32 {
+        self.0
+    }
+
+    pub fn checked_sub(self, rhs: Duration) -> Option<Duration> {
+        self.0.checked_sub(rhs.0).map(Duration)
+    }
+
+    pub fn saturating_sub(self, rhs: Duration) -> Duration {
+        Duration(self.0.saturating_sub(rhs.0))
+    }
+}
+
+impl Add for Duration {
+    type Output = Duration;
+
+    /// Saturates at [`Duration::MAX`].
+    fn add(self, rhs: Duration) -> Duration {
+        Duration(self.0.saturating_add(rhs.0))
+    }
+}
+
+impl AddAssign for Duration {
+    fn add_assign(&mut self, rhs: Duration) {
+        *self = *self + rhs;
+    }
+}
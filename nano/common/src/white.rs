@@ -0,0 +1,143 @@
+//! Tunable white: a warm-white and a cool-white channel, set by colour temperature.
+//!
+//! Colour temperature doesn't mix in a straight line in kelvin. It mixes close
+//! enough to one in mired (a million over the temperature in kelvin), which is the
+//! scale the channels are mixed in here.
+
+use crate::mix::{MixMode, Mixer};
+
+/// Mired equivalent of `kelvin`.
+pub fn mired(kelvin: u16) -> u16 {
+    let kelvin = kelvin.max(1) as u32;
+    ((1_000_000 + kelvin / 2) / kelvin).min(u16::MAX as u32) as u16
+}
+
+/// Kelvin equivalent of `mired`. The conversion goes the same way both ways.
+pub fn kelvin(mired: u16) -> u16 {
+    self::mired(mired)
+}
+
+/// A lamp with a warm and a cool channel, each with a known colour temperature.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TunableWhite {
+    pub warm_kelvin: u16,
+    pub cool_kelvin: u16,
+    pub mode: MixMode,
+}
+
+impl TunableWhite {
+    /// The two temperatures can be given either way round: the lower one is always
+    /// taken as the warm channel.
+    pub const fn new(warm_kelvin: u16, cool_kelvin: u16, mode: MixMode) -> TunableWhite {
+        let (warm_kelvin, cool_kelvin) = if warm_kelvin <= cool_kelvin {
+            (warm_kelvin, cool_kelvin)
+        } else {
+            (cool_kelvin, warm_kelvin)
+        };
+        TunableWhite {
+            warm_kelvin,
+            cool_kelvin,
+            mode,
+        }
+    }
+
+    /// Nearest temperature the lamp can produce.
+    pub fn clamp(&self, kelvin: u16) -> u16 {
+        let (warm, cool) = self.ends();
+        kelvin.max(warm).min(cool)
+    }
+
+    /// `(warm, cool)` brightness for `kelvin`, in the same units as `brightness`.
+    pub fn mix(&self, kelvin: u16, brightness: u16) -> (u16, u16) {
+        let (warm, cool) = self.ends();
+        let warmest = mired(warm);
+        let coolest = mired(cool);
+        let mired = mired(self.clamp(kelvin));
+        Mixer::new(self.mode, warmest - coolest).mix(mired - coolest, brightness)
+    }
+
+    /// Temperature of the light from the channels at `warm` and `cool`, or `None` if
+    /// they're both off.
+    pub fn cct(&self, warm: u16, cool: u16) -> Option<u16> {
+        let total = warm as u32 + cool as u32;
+        if total == 0 {
+            return None;
+        }
+        let (warm_kelvin, cool_kelvin) = self.ends();
+        let mired = (warm as u32 * mired(warm_kelvin) as u32
+            + cool as u32 * mired(cool_kelvin) as u32
+            + total / 2)
+            / total;
+        Some(kelvin(mired as u16))
+    }
+
+    /// `(warm, cool)` temperatures, in order even if the fields were set the wrong way
+    /// round.
+    fn ends(&self) -> (u16, u16) {
+        (
+            self.warm_kelvin.min(self.cool_kelvin),
+            self.warm_kelvin.max(self.cool_kelvin),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAMP: TunableWhite = TunableWhite::new(2700, 6500, MixMode::ConstantTotal);
+
+    #[test]
+    fn converts_to_and_from_mired() {
+        assert_eq!(mired(2700), 370);
+        assert_eq!(mired(6500), 154);
+        assert_eq!(kelvin(250), 4000);
+        assert_eq!(mired(0), u16::MAX);
+    }
+
+    #[test]
+    fn ends_are_one_channel() {
+        assert_eq!(LAMP.mix(2700, 1000), (1000, 0));
+        assert_eq!(LAMP.mix(6500, 1000), (0, 1000));
+        assert_eq!(LAMP.mix(1800, 1000), (1000, 0));
+        assert_eq!(LAMP.mix(10_000, 1000), (0, 1000));
+        assert_eq!(LAMP.cct(1000, 0), Some(2703));
+        assert_eq!(LAMP.cct(0, 0), None);
+    }
+
+    #[test]
+    fn mix_gives_the_temperature_asked_for() {
+        for kelvin in (2700..=6500).step_by(100) {
+            let (warm, cool) = LAMP.mix(kelvin, u16::MAX);
+            let cct = LAMP.cct(warm, cool).unwrap() as i32;
+            assert!(
+                (cct - kelvin as i32).abs() < kelvin as i32 / 100,
+                "{}",
+                kelvin
+            );
+        }
+    }
+
+    #[test]
+    fn ends_given_backwards() {
+        let lamp = TunableWhite::new(6500, 2700, MixMode::ConstantTotal);
+        assert_eq!(lamp, LAMP);
+        let lamp = TunableWhite {
+            warm_kelvin: 6500,
+            cool_kelvin: 2700,
+            ..LAMP
+        };
+        assert_eq!(lamp.clamp(1800), 2700);
+        assert_eq!(lamp.mix(2700, 1000), (1000, 0));
+        assert_eq!(lamp.mix(3817, 1000), (500, 500));
+        assert_eq!(lamp.cct(1000, 0), Some(2703));
+    }
+
+    #[test]
+    fn mixes_in_mired() {
+        // Half way in mired is well short of half way in kelvin
+        assert_eq!(LAMP.mix(3817, 1000), (500, 500));
+        let (warm, cool) = LAMP.mix(4600, 1000);
+        assert!(cool > warm + 200);
+    }
+}
@@ -12,19 +12,24 @@ use arduino_hal::{
     prelude::*,
 };
 use avr_device::interrupt::Mutex;
+use nano_common::{
+    encoder::{CountsPerDetent, QuadratureDecoder},
+    isr::IsrShared,
+    queue::Queue,
+};
 use panic_halt as _;
 
-use core::{
-    cell::Cell,
-    mem::MaybeUninit,
-    sync::atomic::{AtomicBool, Ordering},
-};
+use core::cell::RefCell;
+
+enum Event {
+    Timer,
+    Rotated(i8),
+}
 
-static TMR_OVERFLOW: AtomicBool = AtomicBool::new(false);
-static ROTARY_CHANGE: AtomicBool = AtomicBool::new(false);
-static VAL: Mutex<Cell<u16>> = Mutex::new(Cell::new(0));
-static ROTARY_PINS: Mutex<Cell<MaybeUninit<[Pin<Input<PullUp>, Dynamic>; 2]>>> =
-    Mutex::new(Cell::new(MaybeUninit::uninit()));
+static EVENTS: Queue<Event, 16> = Queue::new();
+static ROTARY_PINS: IsrShared<[Pin<Input<PullUp>, Dynamic>; 2]> = IsrShared::new();
+static DECODER: Mutex<RefCell<QuadratureDecoder>> =
+    Mutex::new(RefCell::new(QuadratureDecoder::new(CountsPerDetent::Four)));
 
 #[arduino_hal::entry]
 fn main() -> ! {
@@ -36,9 +41,7 @@ fn main() -> ! {
         pins.d8.into_pull_up_input().downgrade(),
         pins.d2.into_pull_up_input().downgrade(),
     ];
-    avr_device::interrupt::free(|cs| {
-        ROTARY_PINS.borrow(cs).set(MaybeUninit::new(rotary_pins));
-    });
+    ROTARY_PINS.init(rotary_pins);
 
     let timer1 = peripherals.TC1;
     timer1.tccr1a.write(|w| unsafe { w.bits(0) });
@@ -48,20 +51,38 @@ fn main() -> ! {
     // Enable the timer interrupt
     timer1.timsk1.write(|w| w.ocie1a().set_bit());
 
-    peripherals.EXINT.pcicr.write(|w| unsafe { w.bits(0b100) });
+    // Watch both encoder channels: d8 is PCINT0 and d2 is PCINT18
+    peripherals.EXINT.pcicr.write(|w| unsafe { w.bits(0b101) });
+    peripherals.EXINT.pcmsk0.write(|w| unsafe { w.bits(0b1) });
     peripherals.EXINT.pcmsk2.write(|w| unsafe { w.bits(0b100) });
 
     unsafe {
         avr_device::interrupt::enable();
     }
 
+    let mut val: u16 = 0;
+    let mut dropped = 0;
+
     ufmt::uwriteln!(&mut serial, "Finished Setup!").void_unwrap();
     loop {
-        // if changed(&TMR_OVERFLOW) {
-        //     ufmt::uwriteln!(&mut serial, "Timer!").void_unwrap();
-        // }
-        if changed(&ROTARY_CHANGE) {
-            ufmt::uwriteln!(&mut serial, "Val: {}", get_from_mutex(&VAL)).void_unwrap();
+        while let Some(event) = EVENTS.pop() {
+            match event {
+                Event::Timer => {
+                    ufmt::uwriteln!(&mut serial, "Timer!").void_unwrap();
+                }
+                Event::Rotated(step) => {
+                    val = if step > 0 {
+                        val.saturating_add(1)
+                    } else {
+                        val.saturating_sub(1)
+                    };
+                    ufmt::uwriteln!(&mut serial, "Val: {}", val).void_unwrap();
+                }
+            }
+        }
+        if EVENTS.overflows() != dropped {
+            dropped = EVENTS.overflows();
+            ufmt::uwriteln!(&mut serial, "Dropped events: {}", dropped).void_unwrap();
         }
         delay_ms(50);
     }
@@ -70,38 +91,31 @@ fn main() -> ! {
 #[avr_device::interrupt(atmega328p)]
 #[allow(non_snake_case)]
 fn TIMER1_COMPA() {
-    TMR_OVERFLOW.store(true, Ordering::SeqCst);
+    EVENTS.push(Event::Timer).ok();
 }
 
 #[avr_device::interrupt(atmega328p)]
 #[allow(non_snake_case)]
-fn PCINT2() {
-    avr_device::interrupt::free(|cs| {
-        let rotary_pins = unsafe { &*(&*ROTARY_PINS.borrow(cs).as_ptr()).as_ptr() };
-        if rotary_pins[1].is_low() {
-            ROTARY_CHANGE.store(true, Ordering::SeqCst);
-            let val_cell = VAL.borrow(cs);
-            let val = val_cell.get();
-            if rotary_pins[0].is_low() && val < u16::MAX {
-                val_cell.set(val + 1);
-            } else if rotary_pins[0].is_high() && val > 0 {
-                val_cell.set(val - 1);
-            }
-        }
-    });
+fn PCINT0() {
+    rotary_change();
 }
 
-fn get_from_mutex<T: Copy>(mutex: &Mutex<Cell<T>>) -> T {
-    avr_device::interrupt::free(|cs| mutex.borrow(cs).get())
+#[avr_device::interrupt(atmega328p)]
+#[allow(non_snake_case)]
+fn PCINT2() {
+    rotary_change();
 }
 
-fn changed(var: &AtomicBool) -> bool {
-    avr_device::interrupt::free(|_cs| {
-        if var.load(Ordering::SeqCst) {
-            var.store(false, Ordering::SeqCst);
-            true
-        } else {
-            false
-        }
-    })
+fn rotary_change() {
+    avr_device::interrupt::free(|cs| {
+        ROTARY_PINS.with(cs, |rotary_pins| {
+            let step = DECODER
+                .borrow(cs)
+                .borrow_mut()
+                .update(rotary_pins[1].is_high(), rotary_pins[0].is_high());
+            if step != 0 {
+                EVENTS.push(Event::Rotated(step)).ok();
+            }
+        });
+    });
 }
@@ -1,8 +1,21 @@
 #![no_std]
 #![no_main]
+#![feature(abi_avr_interrupt)]
 
 use panic_halt as _;
 use arduino_hal::prelude::*;
+use nano_common::{
+    dither::MAX_LEVEL,
+    fade::{Easing, FadeDriver},
+    pwm::{Bits, CompareWriter, Mode, Prescaler, TickTimer, Timer1Pwm},
+    time::Duration,
+};
+
+/// Time to go from off to fully on, or back.
+const SWEEP: Duration = Duration::from_millis(500);
+
+static FADER: FadeDriver<[CompareWriter<Timer1Pwm<Bits<8>>>; 1], 1> =
+    FadeDriver::new(SWEEP, Easing::Linear);
 
 #[arduino_hal::entry]
 fn main() -> ! {
@@ -14,27 +27,26 @@ fn main() -> ! {
 
     ufmt::uwriteln!(&mut serial, "Hello from Arduino!").void_unwrap();
 
-    pins.d9.into_output();
-    
-    peripherals.TC1.tccr1a.write(|w| w.wgm1().bits(0b01).com1a().match_clear());
-    peripherals.TC1.tccr1b.write(|w| w.wgm1().bits(0b01).cs1().prescale_64());
-
-    let mut duty = 0;
-    let step = 10;
-    let steps = 255 / step;
+    let timer1 = Timer1Pwm::<Bits<8>>::new(peripherals.TC1, Mode::Fast, Prescaler::Div64);
+    let mut led = timer1.d9(pins.d9.into_output());
+    led.enable();
+    let ticks = TickTimer::timer2(peripherals.TC2, 1_000);
+    FADER.start([led.compare_writer()], ticks.rate());
+    unsafe { avr_device::interrupt::enable() };
 
     loop {
-        for _ in 0..steps {
-            ufmt::uwriteln!(&mut serial, "Duty: {}", duty).void_unwrap();
-            duty += step;
-            peripherals.TC1.ocr1a.write(|w| unsafe { w.bits(duty) });
-            arduino_hal::delay_ms(20);
-        }
-        for _ in 0..steps {
-            ufmt::uwriteln!(&mut serial, "Duty: {}", duty).void_unwrap();
-            duty -= step;
-            peripherals.TC1.ocr1a.write(|w| unsafe { w.bits(duty) });
-            arduino_hal::delay_ms(20);
+        for &level in [MAX_LEVEL, 0].iter() {
+            FADER.fade([level]);
+            while FADER.is_fading() {
+                ufmt::uwriteln!(&mut serial, "Level: {}", FADER.levels()[0]).void_unwrap();
+                arduino_hal::delay_ms(20);
+            }
         }
     }
 }
+
+#[avr_device::interrupt(atmega328p)]
+#[allow(non_snake_case)]
+fn TIMER2_COMPA() {
+    FADER.tick();
+}
@@ -2,6 +2,11 @@
 #![no_main]
 
 use arduino_hal::prelude::*;
+use nano_common::{
+    analog::{AnalogInput, Filter},
+    curve::Curve,
+    pwm::{Bits, Mode, Prescaler, Timer1Pwm},
+};
 use panic_halt as _;
 
 #[arduino_hal::entry]
@@ -12,27 +17,26 @@ fn main() -> ! {
     let mut adc = arduino_hal::Adc::new(peripherals.ADC, Default::default());
 
     let analog_pin = pins.a0.into_analog_input(&mut adc);
-    let _led_pin = pins.d9.into_output();
+    let mut pot = AnalogInput::new(0, 1023)
+        .with_oversampling(2)
+        .with_filter(Filter::Exponential { shift: 2 })
+        .with_dead_band(2);
+    // 10 bits to match the ADC, so the dim end of the curve still has steps to use
+    let timer1 = Timer1Pwm::<Bits<10>>::new(peripherals.TC1, Mode::Fast, Prescaler::Div64);
+    let mut led = timer1.d9(pins.d9.into_output());
+    led.set_curve(Curve::Cie);
+    led.enable();
 
     ufmt::uwriteln!(&mut serial, "Hello from Arduino!").void_unwrap();
 
-    peripherals
-        .TC1
-        .tccr1a
-        .write(|w| w.wgm1().bits(0b01).com1a().match_clear());
-    peripherals
-        .TC1
-        .tccr1b
-        .write(|w| w.wgm1().bits(0b01).cs1().prescale_256());
-
     loop {
-        let pot_val = adc.read_blocking(&analog_pin);
-        let pwm_val = pot_val / 4;
+        if let Some(pot_val) = pot.update(|| adc.read_blocking(&analog_pin)) {
+            led.set_brightness(pot_val, 1023);
 
-        ufmt::uwriteln!(&mut serial, "Pot: {}", pot_val).void_unwrap();
-        ufmt::uwriteln!(&mut serial, "PWM Val: {}", pwm_val).void_unwrap();
+            ufmt::uwriteln!(&mut serial, "Pot: {}", pot_val).void_unwrap();
+            ufmt::uwriteln!(&mut serial, "PWM Val: {}", led.duty().get()).void_unwrap();
+        }
 
-        peripherals.TC1.ocr1a.write(|w| unsafe { w.bits(pwm_val) });
-        arduino_hal::delay_ms(100);
+        arduino_hal::delay_ms(20);
     }
 }
@@ -12,46 +12,57 @@ use arduino_hal::{
     prelude::*,
 };
 use avr_device::interrupt::Mutex;
+use nano_common::{
+    button::{Button, ButtonEvent, GestureTimings},
+    curve::Curve,
+    dither::{self, DitherDriver, LevelWriter},
+    encoder::{AccelerationCurve, Accelerator, CountsPerDetent, QuadratureDecoder},
+    fade::{Easing, FadeDriver},
+    input::{ButtonInput, Command, CommandParser, Dispatcher, InputEvent},
+    isr::IsrShared,
+    light::{ColorModel, LightEngine},
+    millis::Millis,
+    mix::MixMode,
+    pwm::{Icr, Mode, PwmTimer, Timer1Pwm},
+    queue::Queue,
+    time::{Clock, Duration},
+    white::TunableWhite,
+};
 use panic_halt as _;
 
-use core::{
-    cell::Cell,
-    mem::MaybeUninit,
-    sync::atomic::{AtomicBool, Ordering},
+use core::cell::RefCell;
+
+static EVENTS: Queue<InputEvent, 16> = Queue::new();
+static ROTARY_PINS: IsrShared<[Pin<Input<PullUp>, Dynamic>; 2]> = IsrShared::new();
+static CLOCK: IsrShared<Millis> = IsrShared::new();
+static DECODER: Mutex<RefCell<QuadratureDecoder>> =
+    Mutex::new(RefCell::new(QuadratureDecoder::new(CountsPerDetent::Four)));
+static ACCELERATOR: Mutex<RefCell<Accelerator>> =
+    Mutex::new(RefCell::new(Accelerator::new(STEP_CURVE)));
+static DITHER: DitherDriver<Timer1Pwm<Icr>> = DitherDriver::new();
+static FADER: FadeDriver<[LevelWriter<Timer1Pwm<Icr>>; 2], 2> =
+    FadeDriver::new(Duration::from_millis(200), Easing::InOut);
+
+const MAX: u16 = 1023;
+/// Red stands in for warm white and green for cool white.
+const LAMP: TunableWhite = TunableWhite::new(2700, 6500, MixMode::ConstantTotal);
+/// Kelvin per step of the encoder.
+const KELVIN_STEP: i32 = 5;
+/// Well above what a camera's shutter picks up as flicker.
+const PWM_FREQUENCY: u32 = 20_000;
+const STEP_CURVE: AccelerationCurve = AccelerationCurve {
+    slow: Duration::from_millis(150),
+    fast: Duration::from_millis(20),
+    min_step: 4,
+    max_step: 100,
+};
+/// Nothing here uses double or triple clicks, so with no gap to wait out every
+/// press is a click of its own, and toggles the power as soon as it's let go.
+const GESTURES: GestureTimings = GestureTimings {
+    click_gap: Duration::ZERO,
+    long_press: Duration::from_millis(300),
+    repeat: Duration::from_millis(200),
 };
-
-#[allow(dead_code)]
-enum PWMAccuracy {
-    LOW,
-    MEDIUM,
-    HIGH,
-}
-
-impl PWMAccuracy {
-    fn val(&self) -> u16 {
-        match self {
-            PWMAccuracy::LOW => 255,
-            PWMAccuracy::MEDIUM => 511,
-            PWMAccuracy::HIGH => 1023,
-        }
-    }
-}
-
-static ROTARY_CHANGE: AtomicBool = AtomicBool::new(false);
-static TEMP: Mutex<Cell<u16>> = Mutex::new(Cell::new(0));
-static BRIGHTNESS: Mutex<Cell<u16>> = Mutex::new(Cell::new(1));
-static ROTARY_PINS: Mutex<Cell<MaybeUninit<[Pin<Input<PullUp>, Dynamic>; 3]>>> =
-    Mutex::new(Cell::new(MaybeUninit::uninit()));
-static MILLIS_COUNTER: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
-static POWERED: AtomicBool = AtomicBool::new(false);
-
-const PWM_ACCURACY: PWMAccuracy = PWMAccuracy::HIGH;
-const TEMP_STEP: u16 = 25;
-const BRIGHTNESS_STEP: u16 = 25;
-
-const PRESCALER: u32 = 1024;
-const TIMER_COUNTS: u32 = 125;
-const MILLIS_INCREMENT: u32 = PRESCALER * TIMER_COUNTS / 16000;
 
 #[arduino_hal::entry]
 fn main() -> ! {
@@ -62,102 +73,119 @@ fn main() -> ! {
     let rotary_pins = [
         pins.d8.into_pull_up_input().downgrade(),
         pins.d2.into_pull_up_input().downgrade(),
-        pins.d7.into_pull_up_input().downgrade(),
     ];
 
-    avr_device::interrupt::free(|cs| {
-        ROTARY_PINS.borrow(cs).set(MaybeUninit::new(rotary_pins));
-    });
-
-    let _red_led_pin = pins.d9.into_output();
-    let _green_led_pin = pins.d10.into_output();
-
-    let timer1 = peripherals.TC1;
-    timer1.tccr1a.write(|w| {
-        w.wgm1()
-            .bits(match PWM_ACCURACY {
-                PWMAccuracy::LOW => 0b01,
-                PWMAccuracy::MEDIUM => 0b10,
-                PWMAccuracy::HIGH => 0b11,
-            })
-            .com1a()
-            .match_clear()
-            .com1b()
-            .match_clear()
-    });
-    timer1.tccr1b.write(|w| match PWM_ACCURACY {
-        PWMAccuracy::LOW => w.wgm1().bits(0b00).cs1().prescale_256(),
-        PWMAccuracy::MEDIUM => w.wgm1().bits(0b00).cs1().prescale_256(),
-        PWMAccuracy::HIGH => w.wgm1().bits(0b00).cs1().prescale_64(),
-    });
-
-    peripherals.EXINT.pcicr.write(|w| unsafe { w.bits(0b100) });
+    ROTARY_PINS.init(rotary_pins);
+
+    let timer1 = Timer1Pwm::with_frequency(peripherals.TC1, Mode::PhaseCorrect, PWM_FREQUENCY);
+    // The timer only has 8 bits at this frequency, which leaves the lowest
+    // brightnesses stepping visibly without dithering
+    let mut red_led = DITHER.attach(timer1.d9(pins.d9.into_output()));
+    let mut green_led = DITHER.attach(timer1.d10(pins.d10.into_output()));
+    red_led.enable();
+    green_led.enable();
+    // Timer1 overflows once a period, which ticks the fades as well as the dithering
+    FADER.start(
+        [red_led.level_writer(), green_led.level_writer()],
+        timer1.period().frequency(),
+    );
+    let mut lamp = LightEngine::new(&FADER, ColorModel::TunableWhite(LAMP)).with_curve(Curve::Cie);
+
+    // Watch both encoder channels: d8 is PCINT0 and d2 is PCINT18
+    peripherals.EXINT.pcicr.write(|w| unsafe { w.bits(0b101) });
+    peripherals.EXINT.pcmsk0.write(|w| unsafe { w.bits(0b1) });
     peripherals.EXINT.pcmsk2.write(|w| unsafe { w.bits(0b100) });
 
-    millis_init(peripherals.TC0);
-
-    let mut prev_button_state = false;
-    let mut last_up = 0;
-    let mut last_down = 0;
+    let clock = Millis::init(peripherals.TC0);
+    // Times the detents for the acceleration
+    CLOCK.init(clock);
+    let mut knob_button = ButtonInput::new(
+        0,
+        Button::new(pins.d7.into_pull_up_input().downgrade(), clock),
+        GESTURES,
+    );
+    let mut commands = CommandParser::new();
+    let mut dispatcher: Dispatcher<16> = Dispatcher::new();
 
     unsafe {
         avr_device::interrupt::enable();
     }
 
+    let mut kelvin: u16 = LAMP.cool_kelvin;
+    let mut brightness: u16 = 1;
+    let mut powered = false;
+    // Turning adjusts the temperature instead of the brightness while the knob is
+    // held down
+    let mut adjusting_temp = false;
+
+    let period = timer1.period();
+    ufmt::uwriteln!(
+        &mut serial,
+        "PWM: {}Hz, {} bits",
+        period.frequency(),
+        period.resolution_bits()
+    )
+    .void_unwrap();
     ufmt::uwriteln!(&mut serial, "Finished Setup!").void_unwrap();
     loop {
-        // if changed(&TMR_OVERFLOW) {
-        //     ufmt::uwriteln!(&mut serial, "Timer!").void_unwrap();
-        // }
-        if changed(&ROTARY_CHANGE) {
-            let temp = get_from_mutex(&TEMP);
-            let brightness = get_from_mutex(&BRIGHTNESS);
-            ufmt::uwriteln!(&mut serial, "Powered: {}", from_atomic(&POWERED)).void_unwrap();
-            let red =
-                PWM_ACCURACY.val().min(temp) as u32 * brightness as u32 / PWM_ACCURACY.val() as u32;
-            let green = PWM_ACCURACY.val().min((PWM_ACCURACY.val() * 2) - temp) as u32
-                * brightness as u32
-                / PWM_ACCURACY.val() as u32;
+        dispatcher.take_from(&EVENTS);
+        dispatcher.take_from(&mut knob_button);
+        dispatcher.take_from(commands.reading(&mut serial));
+
+        let mut changed = false;
+        for event in &mut dispatcher {
+            match event {
+                InputEvent::Button { event, .. } => match event {
+                    ButtonEvent::Click => {
+                        ufmt::uwriteln!(&mut serial, "Bumped!").void_unwrap();
+                        powered = !powered;
+                    }
+                    ButtonEvent::LongPressStart => adjusting_temp = true,
+                    ButtonEvent::Release => adjusting_temp = false,
+                    _ => continue,
+                },
+                InputEvent::Encoder { delta, .. } => {
+                    if adjusting_temp {
+                        let change = delta as i32 * KELVIN_STEP;
+                        kelvin = LAMP.clamp((kelvin as i32 + change).max(0) as u16);
+                    } else {
+                        brightness = (brightness as i16 + delta).max(1).min(MAX as i16) as u16;
+                    }
+                    powered = true;
+                }
+                InputEvent::Serial(command) => match command {
+                    Command::On => powered = true,
+                    Command::Off => powered = false,
+                    Command::Toggle => powered = !powered,
+                    Command::Brightness(value) => brightness = value.max(1).min(MAX),
+                    Command::Temperature(value) => kelvin = LAMP.clamp(value),
+                },
+                InputEvent::Analog { .. } => continue,
+            }
+            changed = true;
+        }
+
+        if changed {
+            let speed = avr_device::interrupt::free(|cs| {
+                ACCELERATOR.borrow(cs).borrow().velocity(clock.now())
+            });
+            ufmt::uwriteln!(&mut serial, "Powered: {}", powered).void_unwrap();
+            ufmt::uwriteln!(&mut serial, "Speed: {} detents/s", speed).void_unwrap();
+            let now = clock.now();
+            lamp.set_on(powered, now);
+            lamp.set_brightness(dither::level(brightness as u32, MAX as u32), now);
+            lamp.set_kelvin(kelvin, now);
+            let [red, green] = lamp.levels();
             ufmt::uwriteln!(
                 &mut serial,
-                "Brightness: {}\tTemperature: {}\tRed: {}\tGreen: {}",
+                "Brightness: {}\tTemperature: {}K\tRed: {}\tGreen: {}\tCCT: {}K",
                 brightness,
-                temp,
+                kelvin,
                 red,
-                green
+                green,
+                LAMP.cct(red, green).unwrap_or(0)
             )
             .void_unwrap();
-            timer1
-                .ocr1a
-                .write(|w| unsafe { w.bits(if from_atomic(&POWERED) { red as u16 } else { 0 }) });
-            timer1.ocr1b.write(|w| unsafe {
-                w.bits(if from_atomic(&POWERED) {
-                    green as u16
-                } else {
-                    0
-                })
-            });
-        } else {
-            avr_device::interrupt::free(|cs| {
-                let rotary_pins = unsafe { &*(&*ROTARY_PINS.borrow(cs).as_ptr()).as_ptr() };
-                if rotary_pins[2].is_low() && !prev_button_state {
-                    last_down = millis();
-                    prev_button_state = true;
-                } else if rotary_pins[2].is_high() && prev_button_state {
-                    last_up = millis();
-                    let mut diff = last_up - last_down;
-                    if last_up < last_down {
-                        diff += u32::MAX;
-                    }
-                    if diff < 200 {
-                        ufmt::uwriteln!(&mut serial, "Bumped!").void_unwrap();
-
-                        ROTARY_CHANGE.store(true, Ordering::SeqCst);
-                        POWERED.store(!from_atomic(&POWERED), Ordering::SeqCst);
-                    }
-                    prev_button_state = false;
-                }
-            });
         }
         delay_ms(50);
     }
@@ -165,93 +193,37 @@ fn main() -> ! {
 
 #[avr_device::interrupt(atmega328p)]
 #[allow(non_snake_case)]
-fn PCINT2() {
-    avr_device::interrupt::free(|cs| {
-        let rotary_pins = unsafe { &*(&*ROTARY_PINS.borrow(cs).as_ptr()).as_ptr() };
-        if rotary_pins[2].is_low() {
-            // Change Temperature when held down
-            if rotary_pins[0].is_low() {
-                ROTARY_CHANGE.store(true, Ordering::SeqCst);
-                POWERED.store(true, Ordering::SeqCst);
-                let temp_cell = TEMP.borrow(cs);
-                let temp = temp_cell.get();
-                if rotary_pins[1].is_high() {
-                    temp_cell.set((PWM_ACCURACY.val() * 2).min(temp + TEMP_STEP));
-                } else if rotary_pins[1].is_low() {
-                    temp_cell.set(if temp > TEMP_STEP {
-                        temp - TEMP_STEP
-                    } else {
-                        0
-                    });
-                }
-            }
-        } else {
-            // Change brightness
-            if rotary_pins[0].is_low() {
-                ROTARY_CHANGE.store(true, Ordering::SeqCst);
-                POWERED.store(true, Ordering::SeqCst);
-                let brightness_cell = BRIGHTNESS.borrow(cs);
-                let brightness = brightness_cell.get();
-                if rotary_pins[1].is_high() {
-                    brightness_cell.set((PWM_ACCURACY.val()).min(brightness + BRIGHTNESS_STEP));
-                } else if rotary_pins[1].is_low() {
-                    brightness_cell.set(if brightness > BRIGHTNESS_STEP {
-                        brightness - BRIGHTNESS_STEP
-                    } else {
-                        1
-                    });
-                }
-            }
-        }
-    });
-}
-
-fn get_from_mutex<T: Copy>(mutex: &Mutex<Cell<T>>) -> T {
-    avr_device::interrupt::free(|cs| mutex.borrow(cs).get())
+fn TIMER1_OVF() {
+    DITHER.tick();
+    FADER.tick();
 }
 
-fn changed(var: &AtomicBool) -> bool {
-    if from_atomic(var) {
-        var.store(false, Ordering::SeqCst);
-        true
-    } else {
-        false
-    }
+#[avr_device::interrupt(atmega328p)]
+#[allow(non_snake_case)]
+fn PCINT0() {
+    rotary_change();
 }
 
-fn from_atomic(var: &AtomicBool) -> bool {
-    avr_device::interrupt::free(|_cs| var.load(Ordering::SeqCst))
+#[avr_device::interrupt(atmega328p)]
+#[allow(non_snake_case)]
+fn PCINT2() {
+    rotary_change();
 }
 
-fn millis_init(tc0: arduino_hal::pac::TC0) {
-    // Configure the timer for the above interval (in CTC mode)
-    // and enable its interrupt.
-    tc0.tccr0a.write(|w| w.wgm0().ctc());
-    tc0.ocr0a.write(|w| unsafe { w.bits(TIMER_COUNTS as u8) });
-    tc0.tccr0b.write(|w| match PRESCALER {
-        8 => w.cs0().prescale_8(),
-        64 => w.cs0().prescale_64(),
-        256 => w.cs0().prescale_256(),
-        1024 => w.cs0().prescale_1024(),
-        _ => panic!(),
-    });
-    tc0.timsk0.write(|w| w.ocie0a().set_bit());
-
-    // Reset the global millisecond counter
+fn rotary_change() {
     avr_device::interrupt::free(|cs| {
-        MILLIS_COUNTER.borrow(cs).set(0);
+        ROTARY_PINS.with(cs, |rotary_pins| {
+            let step = DECODER
+                .borrow(cs)
+                .borrow_mut()
+                .update(rotary_pins[0].is_high(), rotary_pins[1].is_high());
+            if step == 0 {
+                return;
+            }
+            if let Some(now) = CLOCK.with(cs, |clock| clock.now()) {
+                let delta = ACCELERATOR.borrow(cs).borrow_mut().step(step, now);
+                EVENTS.push(InputEvent::Encoder { id: 0, delta }).ok();
+            }
+        });
     });
 }
-
-#[avr_device::interrupt(atmega328p)]
-fn TIMER0_COMPA() {
-    avr_device::interrupt::free(|cs| {
-        let counter_cell = MILLIS_COUNTER.borrow(cs);
-        let counter = counter_cell.get();
-        counter_cell.set(counter + MILLIS_INCREMENT);
-    })
-}
-
-fn millis() -> u32 {
-    avr_device::interrupt::free(|cs| MILLIS_COUNTER.borrow(cs).get())
-}